@@ -1,2 +1,4 @@
 glslc shaders/shader.vert -o shaders/spv/vert.spv
-glslc shaders/shader.frag -o shaders/spv/frag.spv
\ No newline at end of file
+glslc shaders/shader.frag -o shaders/spv/frag.spv
+glslc -DBINDLESS shaders/shader.frag -o shaders/spv/frag_bindless.spv
+glslc shaders/shader.comp -o shaders/spv/comp.spv
\ No newline at end of file
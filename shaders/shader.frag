@@ -1,9 +1,46 @@
 #version 450
+#ifdef BINDLESS
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
+
+// debug view, matches RenderMode::debug_view
+// 0 shaded, 1 normals, 2 uvs, 3 vertex colors, 4 overdraw
+layout(constant_id = 0) const int DEBUG_VIEW = 0;
+// alpha of the shaded output, below 1 for blended pipelines
+layout(constant_id = 1) const float OPACITY = 1.0;
+
+#ifdef BINDLESS
+// global texture array of BindlessDescriptors, the draw picks its texture by index
+layout(set = 1, binding = 0) uniform sampler2D textures[];
+
+layout(push_constant) uniform Material {
+    uint textureIndex;
+} material;
+
+#define ALBEDO textures[material.textureIndex]
+#else
+layout(set = 1, binding = 0) uniform sampler2D albedo;
+
+#define ALBEDO albedo
+#endif
 
 layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec2 fragUv;
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = vec4(fragColor, 1.0);
+    if (DEBUG_VIEW == 1) {
+        outColor = vec4(normalize(fragNormal) * 0.5 + 0.5, 1.0);
+    } else if (DEBUG_VIEW == 2) {
+        outColor = vec4(fragUv, 0.0, 1.0);
+    } else if (DEBUG_VIEW == 3) {
+        outColor = vec4(fragColor, 1.0);
+    } else if (DEBUG_VIEW == 4) {
+        // drawn additively, every layer adds a bit of heat
+        outColor = vec4(0.1, 0.04, 0.02, 1.0);
+    } else {
+        outColor = vec4(fragColor * texture(ALBEDO, fragUv).rgb, OPACITY);
+    }
 }
@@ -1,11 +1,25 @@
 #version 450
 
+layout(set = 0, binding = 0) uniform Transforms {
+    mat4 model;
+    mat4 view;
+    mat4 projection;
+} transforms;
+
 layout(location = 0) in vec2 inPosition;
 layout(location = 1) in vec3 inColor;
+layout(location = 2) in vec3 inNormal;
+layout(location = 3) in vec2 inUv;
 
 layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out vec2 fragUv;
 
 void main() {
-    gl_Position = vec4(inPosition, 0.0, 1.0);
+    gl_Position = transforms.projection * transforms.view * transforms.model * vec4(inPosition, 0.0, 1.0);
+    // only used by the point render mode
+    gl_PointSize = 3.0;
     fragColor = inColor;
+    fragNormal = mat3(transforms.model) * inNormal;
+    fragUv = inUv;
 }
@@ -0,0 +1,706 @@
+use std::{
+    ffi::c_void,
+    ptr::{self, NonNull},
+};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::{
+    device::DeviceFeatures,
+    memory::{heap_budgets, memory_type_candidates, HeapBudget, MemoryProperties},
+};
+
+/// Size of the device memory blocks allocations are carved out of.
+pub const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// What a range of memory is bound to. Linear and optimal resources next to each other have to be
+/// `bufferImageGranularity` apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationKind {
+    /// buffers and linear tiled images
+    Linear,
+    /// optimal tiled images
+    Optimal,
+}
+
+/// A piece of a device memory block, bind the resource at `memory` + `offset`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub memory_type: u32,
+    /// host address of `offset`, host visible blocks stay mapped for their whole lifetime
+    pub mapped: Option<NonNull<u8>>,
+    /// id of the block inside its memory type
+    block: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Range {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    /// None while the range is free
+    kind: Option<AllocationKind>,
+}
+
+impl Range {
+    fn end(&self) -> vk::DeviceSize {
+        self.offset + self.size
+    }
+}
+
+/// One `vk::DeviceMemory`, split into ranges sorted by offset that always cover the whole block.
+struct Block {
+    id: u64,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    /// start of the mapping, null for device only memory
+    mapped: *mut u8,
+    ranges: Vec<Range>,
+}
+
+impl Block {
+    /// First fit, returns the offset of the new range.
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        kind: AllocationKind,
+        granularity: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        for index in 0..self.ranges.len() {
+            let range = self.ranges[index];
+            if range.kind.is_some() || range.size < size {
+                continue;
+            }
+
+            let mut offset = align_up(range.offset, alignment);
+            // a different kind of resource ending on the same granularity page needs the start pushed to the next page
+            if let Some(previous) = index.checked_sub(1).map(|previous| self.ranges[previous]) {
+                if previous.kind.is_some_and(|previous_kind| previous_kind != kind)
+                    && same_page(previous.end() - 1, offset, granularity)
+                {
+                    offset = align_up(offset, granularity.max(alignment));
+                }
+            }
+            let end = offset + size;
+            if end > range.end() {
+                continue;
+            }
+            // and the same for the resource after this range
+            if let Some(next) = self.ranges.get(index + 1) {
+                if next.kind.is_some_and(|next_kind| next_kind != kind) && same_page(end - 1, next.offset, granularity) {
+                    continue;
+                }
+            }
+
+            // split into [padding][used][rest], empty pieces are left out
+            let mut pieces = vec![];
+            if offset > range.offset {
+                pieces.push(Range {
+                    offset: range.offset,
+                    size: offset - range.offset,
+                    kind: None,
+                });
+            }
+            pieces.push(Range {
+                offset,
+                size,
+                kind: Some(kind),
+            });
+            if end < range.end() {
+                pieces.push(Range {
+                    offset: end,
+                    size: range.end() - end,
+                    kind: None,
+                });
+            }
+            self.ranges.splice(index..=index, pieces);
+            return Some(offset);
+        }
+        None
+    }
+
+    /// Frees the range at `offset` and merges it with free neighbours.
+    fn free(&mut self, offset: vk::DeviceSize) {
+        let Some(mut index) = self
+            .ranges
+            .iter()
+            .position(|range| range.offset == offset && range.kind.is_some())
+        else {
+            debug_assert!(false, "double free at offset {}", offset);
+            return;
+        };
+        self.ranges[index].kind = None;
+
+        if index + 1 < self.ranges.len() && self.ranges[index + 1].kind.is_none() {
+            self.ranges[index].size += self.ranges[index + 1].size;
+            self.ranges.remove(index + 1);
+        }
+        if index > 0 && self.ranges[index - 1].kind.is_none() {
+            self.ranges[index - 1].size += self.ranges[index].size;
+            self.ranges.remove(index);
+            index -= 1;
+        }
+        debug_assert!(self.ranges[index].size > 0);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.iter().all(|range| range.kind.is_none())
+    }
+
+    fn mapped_at(&self, offset: vk::DeviceSize) -> Option<NonNull<u8>> {
+        NonNull::new(self.mapped).map(|mapped| unsafe { mapped.add(offset as usize) })
+    }
+
+    fn used(&self) -> vk::DeviceSize {
+        self.ranges
+            .iter()
+            .filter(|range| range.kind.is_some())
+            .map(|range| range.size)
+            .sum()
+    }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    if alignment <= 1 {
+        value
+    } else {
+        value.div_ceil(alignment) * alignment
+    }
+}
+
+/// True if both byte offsets fall on the same `granularity` sized page.
+fn same_page(a: vk::DeviceSize, b: vk::DeviceSize, granularity: vk::DeviceSize) -> bool {
+    granularity > 1 && a / granularity == b / granularity
+}
+
+/// Usage of one memory type, or of all of them summed up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub blocks: usize,
+    pub allocations: usize,
+    /// device memory taken from the driver
+    pub reserved_bytes: vk::DeviceSize,
+    /// bytes handed out, alignment padding not included
+    pub used_bytes: vk::DeviceSize,
+    pub free_ranges: usize,
+    pub largest_free_range: vk::DeviceSize,
+}
+
+impl MemoryStats {
+    fn add(&mut self, other: &MemoryStats) {
+        self.blocks += other.blocks;
+        self.allocations += other.allocations;
+        self.reserved_bytes += other.reserved_bytes;
+        self.used_bytes += other.used_bytes;
+        self.free_ranges += other.free_ranges;
+        self.largest_free_range = self.largest_free_range.max(other.largest_free_range);
+    }
+}
+
+/// Sub-allocates resources out of large device memory blocks, one list of blocks per memory type.
+///
+/// New blocks are only taken from heaps with budget left, see `memory::heap_budgets`.
+pub struct MemoryAllocator {
+    instance: ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    /// VK_EXT_memory_budget is enabled on the device
+    memory_budget: bool,
+    /// blocks are allocated with the device address flag so any buffer can use SHADER_DEVICE_ADDRESS
+    buffer_device_address: bool,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    block_size: vk::DeviceSize,
+    blocks: Vec<Vec<Block>>,
+    next_block_id: u64,
+}
+
+impl MemoryAllocator {
+    pub unsafe fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, features: &DeviceFeatures) -> Self {
+        Self::with_block_size(instance, physical_device, features, DEFAULT_BLOCK_SIZE)
+    }
+
+    pub unsafe fn with_block_size(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        features: &DeviceFeatures,
+        block_size: vk::DeviceSize,
+    ) -> Self {
+        let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+        let properties = instance.get_physical_device_properties(physical_device);
+
+        Self {
+            instance: instance.clone(),
+            physical_device,
+            memory_budget: features.memory_budget,
+            buffer_device_address: features.buffer_device_address,
+            memory_properties,
+            buffer_image_granularity: properties.limits.buffer_image_granularity,
+            non_coherent_atom_size: properties.limits.non_coherent_atom_size,
+            block_size,
+            blocks: (0..memory_properties.memory_type_count).map(|_| vec![]).collect(),
+            next_block_id: 0,
+        }
+    }
+
+    /// Current budget of every heap, queried from the driver when VK_EXT_memory_budget is enabled.
+    pub unsafe fn heap_budgets(&self) -> Vec<HeapBudget> {
+        let mut own_usage = vec![0; self.memory_properties.memory_heap_count as usize];
+        for (memory_type, blocks) in self.blocks.iter().enumerate() {
+            let heap = self.memory_properties.memory_types[memory_type].heap_index as usize;
+            own_usage[heap] += blocks.iter().map(|block| block.size).sum::<vk::DeviceSize>();
+        }
+        heap_budgets(&self.instance, self.physical_device, self.memory_budget, &own_usage)
+    }
+
+    /// Memory types are tried best first, free space in their blocks before a new block within the heap budget.
+    /// Fails with ERROR_OUT_OF_DEVICE_MEMORY when no memory type has the required properties or fits,
+    /// running out of host memory stops the search right away.
+    pub unsafe fn allocate(
+        &mut self,
+        device: &ash::Device,
+        requirements: vk::MemoryRequirements,
+        properties: impl Into<MemoryProperties>,
+        kind: AllocationKind,
+    ) -> VkResult<Allocation> {
+        let properties = properties.into();
+        let candidates = memory_type_candidates(
+            &self.memory_properties,
+            requirements.memory_type_bits,
+            properties,
+            requirements.size,
+            None,
+        );
+
+        let budgets = self.heap_budgets();
+        for memory_type in candidates {
+            if let Some(allocation) = self.allocate_from_blocks(requirements, memory_type, kind) {
+                return Ok(allocation);
+            }
+            match self.allocate_block(device, requirements, memory_type, kind, &budgets) {
+                Ok(allocation) => return Ok(allocation),
+                // over budget, or the driver disagrees with the budget, try the next type
+                Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+    }
+
+    pub unsafe fn allocate_from_type(
+        &mut self,
+        device: &ash::Device,
+        requirements: vk::MemoryRequirements,
+        memory_type: u32,
+        kind: AllocationKind,
+    ) -> VkResult<Allocation> {
+        if let Some(allocation) = self.allocate_from_blocks(requirements, memory_type, kind) {
+            return Ok(allocation);
+        }
+        let budgets = self.heap_budgets();
+        self.allocate_block(device, requirements, memory_type, kind, &budgets)
+    }
+
+    fn allocate_from_blocks(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        memory_type: u32,
+        kind: AllocationKind,
+    ) -> Option<Allocation> {
+        let granularity = self.buffer_image_granularity;
+        let requirements = self.atom_aligned(requirements, memory_type);
+        self.blocks[memory_type as usize].iter_mut().find_map(|block| {
+            let offset = block.allocate(requirements.size, requirements.alignment, kind, granularity)?;
+            Some(Allocation {
+                memory: block.memory,
+                offset,
+                size: requirements.size,
+                memory_type,
+                mapped: block.mapped_at(offset),
+                block: block.id,
+            })
+        })
+    }
+
+    /// Takes a new block from the driver, resources bigger than a block get a block of their own.
+    /// The block shrinks to what is left of the heap budget, ERROR_OUT_OF_DEVICE_MEMORY if even the
+    /// resource does not fit.
+    unsafe fn allocate_block(
+        &mut self,
+        device: &ash::Device,
+        requirements: vk::MemoryRequirements,
+        memory_type: u32,
+        kind: AllocationKind,
+        budgets: &[HeapBudget],
+    ) -> VkResult<Allocation> {
+        let requirements = self.atom_aligned(requirements, memory_type);
+        let heap = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap as usize].size;
+        let available = budgets.get(heap as usize).map_or(heap_size, HeapBudget::available);
+        if available < requirements.size {
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+        let block_size = self.block_size.min(heap_size / 8).min(available).max(requirements.size);
+
+        let flags_info = vk::MemoryAllocateFlagsInfo {
+            s_type: StructureType::MEMORY_ALLOCATE_FLAGS_INFO,
+            p_next: ptr::null(),
+            flags: vk::MemoryAllocateFlags::DEVICE_ADDRESS,
+            device_mask: 0,
+        };
+        let alloc_info = vk::MemoryAllocateInfo {
+            s_type: StructureType::MEMORY_ALLOCATE_INFO,
+            p_next: if self.buffer_device_address {
+                &flags_info as *const vk::MemoryAllocateFlagsInfo as *const c_void
+            } else {
+                ptr::null()
+            },
+            allocation_size: block_size,
+            memory_type_index: memory_type,
+        };
+        let memory = device.allocate_memory(&alloc_info, None)?;
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        let mapped = if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            match device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) {
+                Ok(mapped) => mapped as *mut u8,
+                Err(e) => {
+                    device.free_memory(memory, None);
+                    return Err(e);
+                }
+            }
+        } else {
+            ptr::null_mut()
+        };
+
+        let mut block = Block {
+            id: self.next_block_id,
+            memory,
+            size: block_size,
+            mapped,
+            ranges: vec![Range {
+                offset: 0,
+                size: block_size,
+                kind: None,
+            }],
+        };
+        self.next_block_id += 1;
+        let offset = block
+            .allocate(requirements.size, requirements.alignment, kind, self.buffer_image_granularity)
+            .expect("a fresh block fits the allocation");
+        let allocation = Allocation {
+            memory,
+            offset,
+            size: requirements.size,
+            memory_type,
+            mapped: block.mapped_at(offset),
+            block: block.id,
+        };
+        self.blocks[memory_type as usize].push(block);
+        Ok(allocation)
+    }
+
+    /// Gives the range back, empty blocks are released except for the last one of a memory type.
+    pub unsafe fn free(&mut self, device: &ash::Device, allocation: Allocation) {
+        let blocks = &mut self.blocks[allocation.memory_type as usize];
+        let Some(index) = blocks.iter().position(|block| block.id == allocation.block) else {
+            debug_assert!(false, "allocation does not belong to this allocator");
+            return;
+        };
+        blocks[index].free(allocation.offset);
+
+        if blocks[index].is_empty() && blocks.len() > 1 {
+            let block = blocks.swap_remove(index);
+            device.free_memory(block.memory, None);
+        }
+    }
+
+    pub fn is_coherent(&self, allocation: &Allocation) -> bool {
+        self.memory_properties.memory_types[allocation.memory_type as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
+
+    /// Makes host writes to `size` bytes at `offset` into the allocation visible to the device.
+    /// Nothing to do for host coherent memory.
+    pub unsafe fn flush(
+        &self,
+        device: &ash::Device,
+        allocation: &Allocation,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> VkResult<()> {
+        if self.is_coherent(allocation) {
+            return Ok(());
+        }
+        device.flush_mapped_memory_ranges(&[self.mapped_range(allocation, offset, size)])
+    }
+
+    /// Makes device writes to `size` bytes at `offset` into the allocation visible to the host.
+    /// Nothing to do for host coherent memory.
+    pub unsafe fn invalidate(
+        &self,
+        device: &ash::Device,
+        allocation: &Allocation,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> VkResult<()> {
+        if self.is_coherent(allocation) {
+            return Ok(());
+        }
+        device.invalidate_mapped_memory_ranges(&[self.mapped_range(allocation, offset, size)])
+    }
+
+    /// The range widened to `nonCoherentAtomSize`. It stays inside the allocation as those are atom aligned
+    /// in non coherent memory, up to the end of the block it becomes WHOLE_SIZE.
+    fn mapped_range(&self, allocation: &Allocation, offset: vk::DeviceSize, size: vk::DeviceSize) -> vk::MappedMemoryRange {
+        let atom = self.non_coherent_atom_size.max(1);
+        let start = (allocation.offset + offset) / atom * atom;
+        let end = align_up(allocation.offset + offset + size, atom).min(allocation.offset + allocation.size);
+        let block_size = self.blocks[allocation.memory_type as usize]
+            .iter()
+            .find(|block| block.id == allocation.block)
+            .map_or(vk::DeviceSize::MAX, |block| block.size);
+
+        vk::MappedMemoryRange {
+            s_type: StructureType::MAPPED_MEMORY_RANGE,
+            p_next: ptr::null(),
+            memory: allocation.memory,
+            offset: start,
+            size: if end >= block_size { vk::WHOLE_SIZE } else { end - start },
+        }
+    }
+
+    /// Non coherent memory gets atom aligned offsets and sizes, so flushing one allocation never touches
+    /// the bytes of its neighbours.
+    fn atom_aligned(&self, mut requirements: vk::MemoryRequirements, memory_type: u32) -> vk::MemoryRequirements {
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) && !flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+            let atom = self.non_coherent_atom_size.max(1);
+            requirements.alignment = requirements.alignment.max(atom);
+            requirements.size = align_up(requirements.size, atom);
+        }
+        requirements
+    }
+
+    /// Creates a buffer and binds it to a new allocation. SHADER_DEVICE_ADDRESS usage fails with
+    /// ERROR_FEATURE_NOT_PRESENT unless `buffer_device_address` is enabled.
+    pub unsafe fn create_buffer(
+        &mut self,
+        device: &ash::Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        properties: impl Into<MemoryProperties>,
+    ) -> VkResult<(vk::Buffer, Allocation)> {
+        if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) && !self.buffer_device_address {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+        let buffer_info = vk::BufferCreateInfo {
+            s_type: StructureType::BUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::BufferCreateFlags::empty(),
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+        let buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+
+        let allocation = match self.allocate(device, requirements, properties, AllocationKind::Linear) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_buffer(buffer, None);
+                return Err(e);
+            }
+        };
+        if let Err(e) = device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) {
+            device.destroy_buffer(buffer, None);
+            self.free(device, allocation);
+            return Err(e);
+        }
+        Ok((buffer, allocation))
+    }
+
+    /// Creates an image and binds it to a new allocation.
+    pub unsafe fn create_image(
+        &mut self,
+        device: &ash::Device,
+        image_info: &vk::ImageCreateInfo,
+        properties: impl Into<MemoryProperties>,
+    ) -> VkResult<(vk::Image, Allocation)> {
+        let image = device.create_image(image_info, None)?;
+        let requirements = device.get_image_memory_requirements(image);
+        let kind = if image_info.tiling == vk::ImageTiling::OPTIMAL {
+            AllocationKind::Optimal
+        } else {
+            AllocationKind::Linear
+        };
+
+        let allocation = match self.allocate(device, requirements, properties, kind) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_image(image, None);
+                return Err(e);
+            }
+        };
+        if let Err(e) = device.bind_image_memory(image, allocation.memory, allocation.offset) {
+            device.destroy_image(image, None);
+            self.free(device, allocation);
+            return Err(e);
+        }
+        Ok((image, allocation))
+    }
+
+    pub fn memory_type_stats(&self, memory_type: u32) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for block in self.blocks[memory_type as usize].iter() {
+            stats.blocks += 1;
+            stats.reserved_bytes += block.size;
+            stats.used_bytes += block.used();
+            for range in block.ranges.iter() {
+                match range.kind {
+                    Some(_) => stats.allocations += 1,
+                    None => {
+                        stats.free_ranges += 1;
+                        stats.largest_free_range = stats.largest_free_range.max(range.size);
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// Summed over every memory type.
+    pub fn stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for memory_type in 0..self.blocks.len() as u32 {
+            stats.add(&self.memory_type_stats(memory_type));
+        }
+        stats
+    }
+
+    /// Every resource has to be destroyed already.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for blocks in self.blocks.iter_mut() {
+            for block in blocks.drain(..) {
+                device.free_memory(block.memory, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: vk::DeviceSize) -> Block {
+        Block {
+            id: 0,
+            memory: vk::DeviceMemory::null(),
+            size,
+            mapped: ptr::null_mut(),
+            ranges: vec![Range {
+                offset: 0,
+                size,
+                kind: None,
+            }],
+        }
+    }
+
+    /// (offset, size, used) of every range
+    fn layout(block: &Block) -> Vec<(vk::DeviceSize, vk::DeviceSize, bool)> {
+        block
+            .ranges
+            .iter()
+            .map(|range| (range.offset, range.size, range.kind.is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn allocate_splits_free_range() {
+        let mut block = block(1024);
+        assert_eq!(block.allocate(100, 16, AllocationKind::Linear, 1), Some(0));
+        assert_eq!(layout(&block), [(0, 100, true), (100, 924, false)]);
+
+        // the alignment leaves a free gap in front
+        assert_eq!(block.allocate(100, 64, AllocationKind::Linear, 1), Some(128));
+        assert_eq!(
+            layout(&block),
+            [(0, 100, true), (100, 28, false), (128, 100, true), (228, 796, false)]
+        );
+
+        // small enough for the gap
+        assert_eq!(block.allocate(28, 4, AllocationKind::Linear, 1), Some(100));
+        assert_eq!(layout(&block)[1], (100, 28, true));
+
+        assert_eq!(block.allocate(797, 1, AllocationKind::Linear, 1), None);
+        assert_eq!(block.allocate(796, 1, AllocationKind::Linear, 1), Some(228));
+        assert!(block.ranges.iter().all(|range| range.kind.is_some()));
+    }
+
+    #[test]
+    fn granularity_separates_linear_and_optimal() {
+        let mut block = block(4096);
+        assert_eq!(block.allocate(100, 16, AllocationKind::Linear, 256), Some(0));
+        // would share the first page with the buffer
+        assert_eq!(block.allocate(100, 16, AllocationKind::Optimal, 256), Some(256));
+        // the same kind only needs its alignment
+        assert_eq!(block.allocate(100, 16, AllocationKind::Optimal, 256), Some(368));
+
+        // fits between the buffer and the first image, on the buffer's page only
+        assert_eq!(block.allocate(50, 16, AllocationKind::Linear, 256), Some(112));
+        // would end on the first image's page
+        assert_eq!(block.allocate(100, 16, AllocationKind::Linear, 256), Some(512));
+        assert_eq!(block.used(), 450);
+    }
+
+    #[test]
+    fn granularity_of_one_never_pads() {
+        let mut block = block(1024);
+        assert_eq!(block.allocate(100, 4, AllocationKind::Linear, 1), Some(0));
+        assert_eq!(block.allocate(100, 4, AllocationKind::Optimal, 1), Some(100));
+        assert_eq!(block.allocate(100, 4, AllocationKind::Linear, 1), Some(200));
+    }
+
+    #[test]
+    fn free_merges_neighbours() {
+        let mut block = block(1024);
+        for offset in [0, 100, 200] {
+            assert_eq!(block.allocate(100, 1, AllocationKind::Linear, 1), Some(offset));
+        }
+
+        block.free(0);
+        assert_eq!(
+            layout(&block),
+            [(0, 100, false), (100, 100, true), (200, 100, true), (300, 724, false)]
+        );
+        // merges with the free rest behind it
+        block.free(200);
+        assert_eq!(layout(&block), [(0, 100, false), (100, 100, true), (200, 824, false)]);
+        // and with both sides at once
+        block.free(100);
+        assert_eq!(layout(&block), [(0, 1024, false)]);
+        assert!(block.is_empty());
+
+        // the merged range is usable as a whole again
+        assert_eq!(block.allocate(1024, 1, AllocationKind::Optimal, 1), Some(0));
+    }
+
+    #[test]
+    fn align_up_and_pages() {
+        assert_eq!(align_up(0, 256), 0);
+        assert_eq!(align_up(1, 256), 256);
+        assert_eq!(align_up(256, 256), 256);
+        assert_eq!(align_up(13, 0), 13);
+        assert!(same_page(0, 255, 256));
+        assert!(!same_page(255, 256, 256));
+        assert!(!same_page(0, 0, 1));
+    }
+}
@@ -0,0 +1,288 @@
+use std::{cell::RefCell, ffi::c_void, ptr, rc::Rc};
+
+use anyhow::{Error, Result};
+use ash::vk::{self, StructureType};
+
+use crate::{
+    deletion::{Deletion, DeletionQueue},
+    descriptor::DescriptorWriter,
+    device::DeviceFeatures,
+    push_constant::PushConstants,
+};
+
+/// `layout(set = N, binding = 0) uniform sampler2D textures[];`
+pub const TEXTURE_BINDING: u32 = 0;
+/// `layout(set = N, binding = 1) buffer Buffers { ... } buffers[];`
+pub const BUFFER_BINDING: u32 = 1;
+
+/// Index of a texture in the global texture array, handed to shaders as a plain integer.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub u32);
+
+// `layout(push_constant) uniform Material { uint textureIndex; }` of shader.frag
+unsafe impl PushConstants for TextureHandle {
+    const STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::FRAGMENT;
+}
+
+/// Index of a buffer in the global storage buffer array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(pub u32);
+
+/// Slots of one global array, released indices get reused.
+struct Slots {
+    capacity: u32,
+    next: u32,
+    free: Vec<u32>,
+}
+
+impl Slots {
+    fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            next: 0,
+            free: vec![],
+        }
+    }
+
+    fn acquire(&mut self) -> Option<u32> {
+        if let Some(index) = self.free.pop() {
+            return Some(index);
+        }
+        if self.next == self.capacity {
+            return None;
+        }
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    fn release(&mut self, index: u32) {
+        debug_assert!(index < self.next && !self.free.contains(&index));
+        self.free.push(index);
+    }
+}
+
+/// A released array index, goes back to its array once the deletion queue is done with it.
+pub struct BindlessSlot {
+    slots: Rc<RefCell<Slots>>,
+    index: u32,
+}
+
+impl BindlessSlot {
+    pub(crate) fn release(self) {
+        self.slots.borrow_mut().release(self.index);
+    }
+}
+
+/// One descriptor set holding every texture and storage buffer, bound once and indexed from the shaders.
+///
+/// Built on descriptor indexing: the arrays are partially bound, so unused slots may stay empty, and
+/// update after bind, so registering does not disturb frames in flight as long as they do not read the slot.
+/// Shaders index with `nonuniformEXT` when the index is not uniform across the draw.
+pub struct BindlessDescriptors {
+    pub set_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    pub set: vk::DescriptorSet,
+    textures: Rc<RefCell<Slots>>,
+    buffers: Rc<RefCell<Slots>>,
+    deletion: DeletionQueue,
+}
+
+impl BindlessDescriptors {
+    /// The array sizes are clamped to the update after bind limits of the device. Released slots go
+    /// through `deletion`. Fails if descriptor indexing was not enabled on the device.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        features: &DeviceFeatures,
+        deletion: &DeletionQueue,
+        max_textures: u32,
+        max_buffers: u32,
+    ) -> Result<Self> {
+        if !features.descriptor_indexing {
+            return Err(Error::msg("bindless descriptors need the descriptor indexing features"));
+        }
+
+        let mut properties_12 = vk::PhysicalDeviceVulkan12Properties::default();
+        let mut properties = vk::PhysicalDeviceProperties2 {
+            p_next: &mut properties_12 as *mut vk::PhysicalDeviceVulkan12Properties as *mut c_void,
+            ..Default::default()
+        };
+        instance.get_physical_device_properties2(physical_device, &mut properties);
+
+        let max_textures = max_textures
+            .min(properties_12.max_descriptor_set_update_after_bind_sampled_images)
+            .min(properties_12.max_per_stage_descriptor_update_after_bind_sampled_images);
+        let max_buffers = max_buffers
+            .min(properties_12.max_descriptor_set_update_after_bind_storage_buffers)
+            .min(properties_12.max_per_stage_descriptor_update_after_bind_storage_buffers);
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: TEXTURE_BINDING,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: max_textures,
+                stage_flags: vk::ShaderStageFlags::ALL,
+                p_immutable_samplers: ptr::null(),
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: BUFFER_BINDING,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: max_buffers,
+                stage_flags: vk::ShaderStageFlags::ALL,
+                p_immutable_samplers: ptr::null(),
+            },
+        ];
+        let array_flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+        // only the highest binding can have a variable count
+        let binding_flags = [
+            array_flags,
+            array_flags | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+        ];
+        let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_BINDING_FLAGS_CREATE_INFO,
+            p_next: ptr::null(),
+            binding_count: binding_flags.len() as u32,
+            p_binding_flags: binding_flags.as_ptr(),
+        };
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: &binding_flags_info as *const vk::DescriptorSetLayoutBindingFlagsCreateInfo as *const c_void,
+            flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+        };
+        let set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: max_textures,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: max_buffers,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+        };
+        let pool = device.create_descriptor_pool(&pool_info, None)?;
+
+        let variable_counts = [max_buffers];
+        let variable_count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_VARIABLE_DESCRIPTOR_COUNT_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_set_count: variable_counts.len() as u32,
+            p_descriptor_counts: variable_counts.as_ptr(),
+        };
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: &variable_count_info as *const vk::DescriptorSetVariableDescriptorCountAllocateInfo as *const c_void,
+            descriptor_pool: pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout,
+        };
+        let set = device.allocate_descriptor_sets(&alloc_info)?[0];
+
+        Ok(Self {
+            set_layout,
+            pool,
+            set,
+            textures: Rc::new(RefCell::new(Slots::new(max_textures))),
+            buffers: Rc::new(RefCell::new(Slots::new(max_buffers))),
+            deletion: deletion.clone(),
+        })
+    }
+
+    /// Puts a sampled image into the texture array, the view has to be in `layout` whenever shaders read it.
+    pub unsafe fn register_texture(
+        &mut self,
+        device: &ash::Device,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout,
+    ) -> Result<TextureHandle> {
+        let mut slots = self.textures.borrow_mut();
+        let index = slots
+            .acquire()
+            .ok_or_else(|| Error::msg(format!("bindless texture array is full ({})", slots.capacity)))?;
+        drop(slots);
+        DescriptorWriter::new()
+            .write_image_element(
+                TEXTURE_BINDING,
+                index,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                image_view,
+                layout,
+                sampler,
+            )
+            .update(device, self.set);
+        Ok(TextureHandle(index))
+    }
+
+    pub unsafe fn register_buffer(
+        &mut self,
+        device: &ash::Device,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Result<BufferHandle> {
+        let mut slots = self.buffers.borrow_mut();
+        let index = slots
+            .acquire()
+            .ok_or_else(|| Error::msg(format!("bindless buffer array is full ({})", slots.capacity)))?;
+        drop(slots);
+        DescriptorWriter::new()
+            .write_buffer_element(
+                BUFFER_BINDING,
+                index,
+                vk::DescriptorType::STORAGE_BUFFER,
+                buffer,
+                offset,
+                range,
+            )
+            .update(device, self.set);
+        Ok(BufferHandle(index))
+    }
+
+    /// The slot is handed out again once the frames in flight that may read it have finished.
+    pub fn release_texture(&mut self, handle: TextureHandle) {
+        self.deletion.push(Deletion::BindlessSlot(BindlessSlot {
+            slots: self.textures.clone(),
+            index: handle.0,
+        }));
+    }
+
+    pub fn release_buffer(&mut self, handle: BufferHandle) {
+        self.deletion.push(Deletion::BindlessSlot(BindlessSlot {
+            slots: self.buffers.clone(),
+            index: handle.0,
+        }));
+    }
+
+    /// Binds the global set as `layout(set = set_index)`, `layout` has to include `set_layout` there.
+    pub unsafe fn bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+        set_index: u32,
+    ) {
+        device.cmd_bind_descriptor_sets(command_buffer, bind_point, layout, set_index, &[self.set], &[]);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
@@ -0,0 +1,71 @@
+use ash::vk;
+
+/// Common blend equations for a color attachment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendPreset {
+    /// blending off, the fragment replaces what is there
+    #[default]
+    Opaque,
+    /// src * a + dst * (1 - a), for straight (non premultiplied) alpha
+    Alpha,
+    /// src + dst * (1 - a), the color is already multiplied by its alpha
+    PremultipliedAlpha,
+    /// src * a + dst, glow, particles and the like
+    Additive,
+    /// src * dst, darkens what is behind it
+    Multiply,
+}
+
+impl BlendPreset {
+    /// Anything that reads the destination has to be drawn after the opaque geometry.
+    pub fn is_transparent(&self) -> bool {
+        *self != BlendPreset::Opaque
+    }
+
+    pub fn attachment_state(&self) -> vk::PipelineColorBlendAttachmentState {
+        // (src color, dst color, src alpha, dst alpha)
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            BlendPreset::Opaque => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+            ),
+            BlendPreset::Alpha => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendPreset::PremultipliedAlpha => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendPreset::Additive => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::ONE,
+            ),
+            BlendPreset::Multiply => (
+                vk::BlendFactor::DST_COLOR,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::DST_ALPHA,
+                vk::BlendFactor::ZERO,
+            ),
+        };
+
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: self.is_transparent() as vk::Bool32,
+            src_color_blend_factor: src_color,
+            dst_color_blend_factor: dst_color,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: src_alpha,
+            dst_alpha_blend_factor: dst_alpha,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+}
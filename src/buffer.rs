@@ -1,48 +1,51 @@
-use std::{
-    ffi::c_void,
-    mem::{size_of, size_of_val},
-    ptr,
-};
+use std::{ffi::c_void, mem::size_of, ptr};
 
 use anyhow::Result;
 use ash::{
     prelude::VkResult,
-    vk::{self, BufferUsageFlags, ImageCreateFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+    vk::{self, ImageCreateFlags, MemoryPropertyFlags, StructureType},
 };
 
 use crate::{
-    constant::{Index, Vertex, INDICES, VERTICES},
+    memory::{select_memory_type, MemoryProperties},
+    render_pass::FramebufferAttachment,
+    render_queue::RenderQueue,
+    rendering::{begin_frame_target, end_frame_target, FrameTarget},
     QueueFamilyIndices,
 };
 
 pub const MAX_FRAMES_IN_FLIGHT: u8 = 2;
 
+/// One framebuffer per swapchain image, `attachments` follows the order of the render pass attachments.
 pub unsafe fn create_frame_buffer(
     device: &ash::Device,
     swapchain_image_views: &Vec<vk::ImageView>,
+    attachments: &[FramebufferAttachment],
     render_pass: vk::RenderPass,
     swapchain_extent: vk::Extent2D,
 ) -> VkResult<Vec<vk::Framebuffer>> {
     let mut frame_buffer = vec![];
     println!("frame_buffer length = {}", swapchain_image_views.len());
     for index in 0..swapchain_image_views.len() {
-        let attachments = [swapchain_image_views[index]];
-        let mut info = vk::FramebufferCreateInfo {
+        let views: Vec<vk::ImageView> = attachments
+            .iter()
+            .map(|attachment| match attachment {
+                FramebufferAttachment::Swapchain => swapchain_image_views[index],
+                FramebufferAttachment::View(view) => *view,
+            })
+            .collect();
+        let info = vk::FramebufferCreateInfo {
             s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
             p_next: ptr::null(),
             flags: vk::FramebufferCreateFlags::empty(),
             render_pass,
-            attachment_count: 1,
-            p_attachments: attachments.as_ptr(),
+            attachment_count: views.len() as u32,
+            p_attachments: views.as_ptr(),
             width: swapchain_extent.width,
             height: swapchain_extent.height,
             layers: 1,
         };
 
-        info.width = swapchain_extent.width;
-        info.height = swapchain_extent.height;
-        info.layers = 1;
-
         let frame = device.create_framebuffer(&info, None)?;
         frame_buffer.push(frame);
     }
@@ -65,13 +68,11 @@ pub unsafe fn create_command_buffers(device: &ash::Device, command_pool: vk::Com
 pub unsafe fn record_command_buffer(
     device: &ash::Device,
     command_buffer: vk::CommandBuffer,
-    render_pass: vk::RenderPass,
-    swap_chain_framebuffer: &Vec<vk::Framebuffer>,
-    image_index: u32,
+    target: &FrameTarget,
     swapchain_extent: vk::Extent2D,
-    pipeline: vk::Pipeline,
-    vertex_buffer: vk::Buffer,
-    index_buffer: vk::Buffer,
+    layout: vk::PipelineLayout,
+    descriptor_sets: &[vk::DescriptorSet],
+    queue: &RenderQueue,
 ) -> VkResult<()> {
     let begin_info = vk::CommandBufferBeginInfo {
         s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
@@ -82,31 +83,8 @@ pub unsafe fn record_command_buffer(
 
     device.begin_command_buffer(command_buffer, &begin_info)?;
 
-    let clear_values = [vk::ClearValue {
-        // draw the frame black before drawing the scene
-        color: vk::ClearColorValue {
-            float32: [0.0, 0.0, 0.0, 1.0],
-        },
-    }];
-
-    let render_pass_info = vk::RenderPassBeginInfo {
-        s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
-        p_next: ptr::null(),
-        render_pass,
-        framebuffer: swap_chain_framebuffer[image_index as usize],
-        render_area: vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain_extent,
-        },
-        clear_value_count: 1,
-        p_clear_values: clear_values.as_ptr(),
-    };
-
-    // // Begin the render pass
-
-    device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
-
-    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    // render pass or dynamic rendering
+    begin_frame_target(device, command_buffer, target, swapchain_extent);
 
     let mut viewport = vk::Viewport::default();
     viewport.x = 0.0; // bottom left corner
@@ -125,15 +103,23 @@ pub unsafe fn record_command_buffer(
     }];
     device.cmd_set_scissor(command_buffer, 0, &scissor);
 
-    let vertex_buffers = [vertex_buffer];
-    let offsets = [0];
-    device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
-    device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT16);
+    // per frame sets, shared by every pipeline using `layout`
+    if !descriptor_sets.is_empty() {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            layout,
+            0,
+            descriptor_sets,
+            &[],
+        );
+    }
 
-    device.cmd_draw_indexed(command_buffer, INDICES.len() as u32, 1, 0, 0, 0);
+    // opaque draws, then the blended ones
+    queue.record(device, command_buffer, layout);
 
     // End the render pass
-    device.cmd_end_render_pass(command_buffer);
+    end_frame_target(device, command_buffer, target);
 
     device.end_command_buffer(command_buffer).expect("failed to record");
 
@@ -188,151 +174,25 @@ pub unsafe fn create_sync_objects(
     Ok((inflight_fences, image_available_semaphores, render_finished_semaphores))
 }
 
-pub unsafe fn create_index_buffer(
-    device: &ash::Device,
-    instance: &ash::Instance,
-    physical_device: vk::PhysicalDevice,
-    transfer_pool: vk::CommandPool,
-    transfer_queue: vk::Queue,
-) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
-    let buffer_size = std::mem::size_of_val(&INDICES) as u64;
-
-    let (staging_buffer, stage_memory) = create_buffer(
-        device,
-        instance,
-        physical_device,
-        buffer_size,
-        BufferUsageFlags::TRANSFER_SRC,
-        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-    )?;
-
-    let data = device.map_memory(stage_memory, 0, buffer_size, MemoryMapFlags::empty())? as *mut Index;
-    data.copy_from_nonoverlapping(INDICES.as_ptr(), INDICES.len());
-    device.unmap_memory(stage_memory);
-
-    let (index_buffer, index_buffer_memory) = create_buffer(
-        device,
-        instance,
-        physical_device,
-        buffer_size,
-        BufferUsageFlags::INDEX_BUFFER | BufferUsageFlags::TRANSFER_DST,
-        MemoryPropertyFlags::DEVICE_LOCAL,
-    )?;
-
-    copy_buffer(
-        device,
-        staging_buffer,
-        index_buffer,
-        buffer_size,
-        transfer_pool,
-        transfer_queue,
-    )?;
-
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(stage_memory, None);
-    Ok((index_buffer, index_buffer_memory))
-}
-
-pub unsafe fn create_vertex_buffer(
-    device: &ash::Device,
-    physical_device: vk::PhysicalDevice,
-    instance: &ash::Instance,
-    transfer_pool: vk::CommandPool,
-    transfer_queue: vk::Queue,
-) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
-    let properties_vertex = MemoryPropertyFlags::DEVICE_LOCAL;
-    let properties_staging = MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT;
-    let buffer_size = (size_of_val(&VERTICES)) as u64;
-
-    let (stage_buffer, stage_buffer_memory) = create_buffer(
-        device,
-        instance,
-        physical_device,
-        buffer_size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        properties_staging,
-    )?;
-
-    let data = device.map_memory(stage_buffer_memory, 0, buffer_size, MemoryMapFlags::empty())? as *mut Vertex;
-    data.copy_from_nonoverlapping(VERTICES.as_ptr(), VERTICES.len());
-    device.unmap_memory(stage_buffer_memory);
-
-    let (vertex_buffer, vertex_buffer_memory) = create_buffer(
-        device,
-        instance,
-        physical_device,
-        buffer_size,
-        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-        properties_vertex,
-    )?;
-
-    copy_buffer(
-        device,
-        stage_buffer,
-        vertex_buffer,
-        buffer_size,
-        transfer_pool,
-        transfer_queue,
-    )?;
-
-    device.destroy_buffer(stage_buffer, None);
-    device.free_memory(stage_buffer_memory, None);
-
-    Ok((vertex_buffer, vertex_buffer_memory))
-}
-
-unsafe fn copy_buffer(
-    device: &ash::Device,
-    src: vk::Buffer,
-    dst: vk::Buffer,
-    size: vk::DeviceSize,
-    transfer_pool: vk::CommandPool,
-    transfer_queue: vk::Queue,
-) -> VkResult<()> {
-    let command_buffer = begin_single_commands(device, transfer_pool)?;
-
-    let copy_regions = [vk::BufferCopy {
-        src_offset: 0,
-        dst_offset: 0,
-        size,
-    }];
-
-    device.cmd_copy_buffer(command_buffer, src, dst, &copy_regions);
-
-    end_single_time_command(device, command_buffer, transfer_pool, transfer_queue)?;
-
-    Ok(())
-}
-
+/// ERROR_OUT_OF_DEVICE_MEMORY when no memory type has the required properties, budgets are not checked here.
 unsafe fn find_memory_type(
     type_filter: u32,
-    properties: vk::MemoryPropertyFlags,
+    properties: impl Into<MemoryProperties>,
     physical_device: vk::PhysicalDevice,
     instance: &ash::Instance,
-) -> u32 {
-    let mut memproperties = vk::PhysicalDeviceMemoryProperties2::default();
-    instance.get_physical_device_memory_properties2(physical_device, &mut memproperties);
-
-    for i in 0..memproperties.memory_properties.memory_type_count {
-        let is_memory_type_bits = type_filter & (1 << i) > 0;
-        let is_property = memproperties.memory_properties.memory_types[i as usize]
-            .property_flags
-            .contains(properties);
-
-        if is_memory_type_bits && is_property {
-            return i;
-        }
-    }
-    panic!("failed to fidnd suitable memory type!");
+) -> VkResult<u32> {
+    let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+    select_memory_type(&memory_properties, type_filter, properties.into(), 0, None)
 }
 
-unsafe fn create_buffer(
+/// Simple path with one `allocate_memory` per buffer, `allocator::MemoryAllocator` sub-allocates instead.
+pub unsafe fn create_buffer(
     device: &ash::Device,
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
-    properties: vk::MemoryPropertyFlags,
+    properties: impl Into<MemoryProperties>,
 ) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
     let buffer_info = vk::BufferCreateInfo {
         s_type: StructureType::BUFFER_CREATE_INFO,
@@ -352,7 +212,7 @@ unsafe fn create_buffer(
         s_type: StructureType::MEMORY_ALLOCATE_INFO,
         p_next: ptr::null(),
         allocation_size: mem_requirement.size,
-        memory_type_index: find_memory_type(mem_requirement.memory_type_bits, properties, physical_device, instance),
+        memory_type_index: find_memory_type(mem_requirement.memory_type_bits, properties, physical_device, instance)?,
     };
 
     let device_memory = device.allocate_memory(&alloc_info, None)?;
@@ -361,74 +221,47 @@ unsafe fn create_buffer(
     Ok((buffer, device_memory))
 }
 
-use stb_image::image::{self, LoadResult};
-
-fn load_texture(path: &str) -> Result<(Vec<u8>, u32, u32), String> {
-    let (width, height, data) = match image::load(path) {
-        LoadResult::Error(_) => todo!(),
-        LoadResult::ImageU8(x) => (x.width as u32, x.height as u32, x.data),
-        LoadResult::ImageF32(x) => panic!("no "),
-    };
-
-    Ok((data, width, height))
-}
-
-// Create Vulkan image from loaded texture data
-unsafe fn create_texture_image(
+/// Simple path with one `allocate_memory` per image, `allocator::MemoryAllocator` sub-allocates instead.
+pub unsafe fn create_image(
     device: &ash::Device,
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
-    texture_data: &[u8],
     width: u32,
     height: u32,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: MemoryPropertyFlags,
 ) -> VkResult<(vk::Image, vk::DeviceMemory)> {
-    let image_size = (width * height * 4) as vk::DeviceSize;
-
-    let (stage_buffer, stage_memory) = create_buffer(
-        device,
-        instance,
-        physical_device,
-        image_size,
-        BufferUsageFlags::TRANSFER_SRC,
-        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-    )?;
-
-    let data = device.map_memory(stage_memory, 0, image_size, MemoryMapFlags::empty())? as *mut u8;
-    data.copy_from_nonoverlapping(texture_data.as_ptr(), VERTICES.len());
-    device.unmap_memory(stage_memory);
-
-    let (image, image_memory) = create_image(
-        device,
-        instance,
-        physical_device,
-        texture_data,
-        width,
-        height,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-        MemoryPropertyFlags::DEVICE_LOCAL,
-    )?;
+    let image_info = image_create_info(width, height, format, samples, tiling, usage);
+    let image = device.create_image(&image_info, None)?;
+
+    let mem_requirement = device.get_image_memory_requirements(image);
+
+    let alloc_info = vk::MemoryAllocateInfo {
+        s_type: StructureType::MEMORY_ALLOCATE_INFO,
+        p_next: ptr::null(),
+        allocation_size: mem_requirement.size,
+        memory_type_index: find_memory_type(mem_requirement.memory_type_bits, properties, physical_device, instance)?,
+    };
 
+    let image_memory = device.allocate_memory(&alloc_info, None)?;
+    device.bind_image_memory(image, image_memory, 0)?;
     Ok((image, image_memory))
 }
 
-unsafe fn create_image(
-    device: &ash::Device,
-    instance: &ash::Instance,
-    physical_device: vk::PhysicalDevice,
-    texture_data: &[u8],
+/// Single mip, single layer 2d image starting out UNDEFINED.
+pub fn image_create_info(
     width: u32,
     height: u32,
     format: vk::Format,
+    samples: vk::SampleCountFlags,
     tiling: vk::ImageTiling,
     usage: vk::ImageUsageFlags,
-    properties: MemoryPropertyFlags,
-) -> VkResult<(vk::Image, vk::DeviceMemory)> {
-    let image_size = (width * height * 4) as vk::DeviceSize;
-
+) -> vk::ImageCreateInfo {
     let extent = vk::Extent3D { width, height, depth: 1 };
-    let image_info = vk::ImageCreateInfo {
+    vk::ImageCreateInfo {
         s_type: StructureType::IMAGE_CREATE_INFO,
         p_next: ptr::null(),
         flags: ImageCreateFlags::empty(),
@@ -437,119 +270,40 @@ unsafe fn create_image(
         extent: extent,
         mip_levels: 1,
         array_layers: 1,
-        samples: vk::SampleCountFlags::TYPE_1,
+        samples,
         tiling,
         usage,
         sharing_mode: vk::SharingMode::EXCLUSIVE,
         queue_family_index_count: 0,
         p_queue_family_indices: ptr::null(),
         initial_layout: vk::ImageLayout::UNDEFINED,
-    };
-
-    let image = device.create_image(&image_info, None)?;
-
-    let mem_requirement = device.get_image_memory_requirements(image);
-
-    let alloc_info = vk::MemoryAllocateInfo {
-        s_type: StructureType::MEMORY_ALLOCATE_INFO,
-        p_next: ptr::null(),
-        allocation_size: mem_requirement.size,
-        memory_type_index: find_memory_type(mem_requirement.memory_type_bits, properties, physical_device, instance),
-    };
-
-    let image_memory = device.allocate_memory(&alloc_info, None)?;
-    device.bind_image_memory(image, image_memory, 0)?;
-    Ok((image, image_memory))
-}
-
-unsafe fn begin_single_commands(device: &ash::Device, command_pool: vk::CommandPool) -> VkResult<(vk::CommandBuffer)> {
-    let alloc_info = vk::CommandBufferAllocateInfo {
-        s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
-        p_next: ptr::null(),
-        command_pool: command_pool,
-        level: vk::CommandBufferLevel::PRIMARY,
-        command_buffer_count: 1,
-    };
-    let command_buffer = device.allocate_command_buffers(&alloc_info)?;
-
-    let begin_info = vk::CommandBufferBeginInfo {
-        s_type: StructureType::COMMAND_BUFFER_BEGIN_INFO,
-        p_next: ptr::null(),
-        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
-        p_inheritance_info: ptr::null(),
-    };
-
-    device.begin_command_buffer(command_buffer[0], &begin_info)?;
-
-    Ok((command_buffer[0]))
-}
-
-unsafe fn end_single_time_command(
-    device: &ash::Device,
-    command_buffer: vk::CommandBuffer,
-    command_pool: vk::CommandPool,
-    queue: vk::Queue,
-) -> VkResult<()> {
-    device.end_command_buffer(command_buffer)?;
-
-    let submit_info = [vk::SubmitInfo {
-        s_type: StructureType::SUBMIT_INFO,
-        p_next: ptr::null(),
-        wait_semaphore_count: 0,
-        p_wait_semaphores: ptr::null(),
-        p_wait_dst_stage_mask: ptr::null(),
-        command_buffer_count: 1,
-        p_command_buffers: &command_buffer,
-        signal_semaphore_count: 0,
-        p_signal_semaphores: ptr::null(),
-    }];
-
-    device.queue_submit(queue, &submit_info, vk::Fence::null())?;
-    device.queue_wait_idle(queue)?;
-
-    device.free_command_buffers(command_pool, &[command_buffer]);
-
-    Ok(())
+    }
 }
 
-unsafe fn transition_image_layout(
+/// 2d view of the first `mip_levels` levels.
+pub unsafe fn create_image_view(
     device: &ash::Device,
-    command_pool: vk::CommandPool,
     image: vk::Image,
     format: vk::Format,
-    old_layout: vk::ImageLayout,
-    new_layout: vk::ImageLayout,
-) -> VkResult<()> {
-    let command_buffer = begin_single_commands(device, command_pool)?;
-
-    let barrier = vk::ImageMemoryBarrier {
-        s_type: StructureType::IMAGE_MEMORY_BARRIER,
+    aspect_mask: vk::ImageAspectFlags,
+    mip_levels: u32,
+) -> VkResult<vk::ImageView> {
+    let view_info = vk::ImageViewCreateInfo {
+        s_type: StructureType::IMAGE_VIEW_CREATE_INFO,
         p_next: ptr::null(),
-        src_access_mask: vk::AccessFlags::empty(),
-        dst_access_mask: vk::AccessFlags::empty(),
-        old_layout,
-        new_layout,
-        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        flags: vk::ImageViewCreateFlags::empty(),
         image,
+        view_type: vk::ImageViewType::TYPE_2D,
+        format,
+        components: vk::ComponentMapping::default(),
         subresource_range: vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
+            aspect_mask,
             base_mip_level: 0,
-            level_count: 1,
+            level_count: mip_levels,
             base_array_layer: 0,
             layer_count: 1,
         },
     };
 
-    // device.cmd_pipeline_barrier(
-    //     command_buffer,
-    //     vk::PipelineStageFlags::empty(),
-    //     vk::PipelineStageFlags::empty(),
-    //     vk::DependencyFlags::empty(),
-    //     ptr::null(),
-    //     ptr::null(),
-    //     &[barrier],
-    // );
-
-    Ok(())
+    device.create_image_view(&view_info, None)
 }
@@ -0,0 +1,759 @@
+use std::{collections::HashMap, ptr};
+
+use anyhow::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::{
+    buffer::create_command_pool,
+    descriptor::{DescriptorAllocator, DescriptorBinding, DescriptorLayoutCache, DescriptorWriter},
+    pipeline::{create_shader_module, SpecializationConstants},
+    utility, FamilyQueue,
+};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const SPIRV_HEADER_WORDS: usize = 5;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_CONSTANT: u32 = 43;
+const OP_CONSTANT_COMPOSITE: u32 = 44;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_SPEC_CONSTANT_COMPOSITE: u32 = 51;
+const OP_DECORATE: u32 = 71;
+const OP_EXECUTION_MODE_ID: u32 = 331;
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+const EXECUTION_MODE_LOCAL_SIZE_ID: u32 = 38;
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_BUILT_IN: u32 = 11;
+const BUILT_IN_WORKGROUP_SIZE: u32 = 25;
+
+/// Reads the workgroup size the compute shader was compiled with. Sizes from specialization constants
+/// (`local_size_x_id` in glsl) take their value from `constants`, or the shader's default without one.
+/// Returns None if the module is not spir-v or declares no size.
+pub fn reflect_local_size(spirv: &[u8], constants: &SpecializationConstants) -> Option<[u32; 3]> {
+    if !spirv.len().is_multiple_of(4) {
+        return None;
+    }
+    let words: Vec<u32> = spirv
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+
+    if words.len() < SPIRV_HEADER_WORDS || words[0] != SPIRV_MAGIC {
+        return None;
+    }
+
+    let mut literal = None;
+    let mut local_size_ids = None;
+    let mut workgroup_size = None;
+    // result id to the value of scalar constants, the constituents of 3 component ones and the SpecId
+    let mut scalars = HashMap::new();
+    let mut composites = HashMap::new();
+    let mut spec_ids = HashMap::new();
+
+    // every instruction starts with (word count << 16 | opcode)
+    let mut index = SPIRV_HEADER_WORDS;
+    while index < words.len() {
+        let word_count = (words[index] >> 16) as usize;
+        let opcode = words[index] & 0xFFFF;
+        if word_count == 0 || index + word_count > words.len() {
+            return None;
+        }
+        let operands = &words[index + 1..index + word_count];
+
+        match (opcode, operands) {
+            // OpExecutionMode %entry LocalSize x y z
+            (OP_EXECUTION_MODE, [_, EXECUTION_MODE_LOCAL_SIZE, x, y, z, ..]) => literal = Some([*x, *y, *z]),
+            // OpExecutionModeId %entry LocalSizeId %x %y %z
+            (OP_EXECUTION_MODE_ID, [_, EXECUTION_MODE_LOCAL_SIZE_ID, x, y, z, ..]) => local_size_ids = Some([*x, *y, *z]),
+            // OpDecorate %id SpecId n, OpDecorate %id BuiltIn WorkgroupSize
+            (OP_DECORATE, [id, DECORATION_SPEC_ID, spec_id, ..]) => {
+                spec_ids.insert(*id, *spec_id);
+            }
+            (OP_DECORATE, [id, DECORATION_BUILT_IN, BUILT_IN_WORKGROUP_SIZE, ..]) => workgroup_size = Some(*id),
+            // %id = OpConstant %type value, only the low word matters for 32 bit sizes
+            (OP_CONSTANT | OP_SPEC_CONSTANT, [_, id, value, ..]) => {
+                scalars.insert(*id, *value);
+            }
+            (OP_CONSTANT_COMPOSITE | OP_SPEC_CONSTANT_COMPOSITE, [_, id, x, y, z]) => {
+                composites.insert(*id, [*x, *y, *z]);
+            }
+            _ => {}
+        }
+        index += word_count;
+    }
+
+    let scalar = |id: u32| match spec_ids.get(&id).and_then(|spec_id| constants.get(*spec_id)) {
+        Some(value) => Some(value.to_bits()),
+        None => scalars.get(&id).copied(),
+    };
+    let resolve = |[x, y, z]: [u32; 3]| Some([scalar(x)?, scalar(y)?, scalar(z)?]);
+
+    // the WorkgroupSize builtin wins over the execution modes
+    match (workgroup_size, local_size_ids) {
+        (Some(id), _) => resolve(*composites.get(&id)?),
+        (None, Some(ids)) => resolve(ids),
+        (None, None) => literal,
+    }
+}
+
+/// Number of workgroups needed to cover `problem_size` invocations.
+pub fn group_count(problem_size: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    [
+        problem_size[0].div_ceil(local_size[0].max(1)),
+        problem_size[1].div_ceil(local_size[1].max(1)),
+        problem_size[2].div_ceil(local_size[2].max(1)),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComputeBinding {
+    /// `buffer` block in glsl
+    StorageBuffer,
+    /// `image2D` etc, has to be in the GENERAL layout when dispatched
+    StorageImage,
+}
+
+impl ComputeBinding {
+    fn descriptor_type(self) -> vk::DescriptorType {
+        match self {
+            ComputeBinding::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+            ComputeBinding::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+        }
+    }
+}
+
+pub struct ComputePipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    /// owned by the layout cache passed to `new`
+    pub set_layout: vk::DescriptorSetLayout,
+    /// allocated from the allocator passed to `new`
+    pub descriptor_set: vk::DescriptorSet,
+    bindings: Vec<ComputeBinding>,
+    /// buffer, offset and range bound to each storage buffer binding, handed between families by `run`
+    buffers: Vec<Option<(vk::Buffer, vk::DeviceSize, vk::DeviceSize)>>,
+    /// workgroup size declared in the shader, after specialization
+    pub local_size: [u32; 3],
+}
+
+impl ComputePipeline {
+    /// `bindings[i]` is `layout(set = 0, binding = i)` in the shader. `constants` specialize the shader,
+    /// including its workgroup size.
+    pub unsafe fn new(
+        device: &ash::Device,
+        layouts: &mut DescriptorLayoutCache,
+        descriptors: &mut DescriptorAllocator,
+        shader_path: &str,
+        bindings: &[ComputeBinding],
+        constants: &SpecializationConstants,
+    ) -> Result<Self> {
+        let bytes = utility::read_file(shader_path)?;
+        let local_size =
+            reflect_local_size(&bytes, constants).ok_or_else(|| Error::msg(format!("{} has no local size", shader_path)))?;
+
+        let layout_bindings: Vec<DescriptorBinding> = bindings
+            .iter()
+            .enumerate()
+            .map(|(index, binding)| {
+                DescriptorBinding::new(index as u32, binding.descriptor_type(), vk::ShaderStageFlags::COMPUTE)
+            })
+            .collect();
+        let set_layout = layouts.get(device, &layout_bindings)?;
+        let descriptor_set = if bindings.is_empty() {
+            vk::DescriptorSet::null()
+        } else {
+            descriptors.allocate(device, layouts, set_layout)?
+        };
+
+        let set_layouts = [set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            s_type: StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineLayoutCreateFlags::empty(),
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: 0,
+            p_push_constant_ranges: ptr::null(),
+        };
+        let layout = device.create_pipeline_layout(&layout_info, None)?;
+        let shader = match create_shader_module(device, bytes) {
+            Ok(shader) => shader,
+            Err(e) => {
+                device.destroy_pipeline_layout(layout, None);
+                return Err(e);
+            }
+        };
+
+        let entry_point_name = std::ffi::CString::new("main").expect("CString::new failed");
+        let specialization_data = constants.build();
+        let specialization_info = specialization_data.info();
+
+        let info = vk::ComputePipelineCreateInfo {
+            s_type: StructureType::COMPUTE_PIPELINE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineCreateFlags::empty(),
+            stage: vk::PipelineShaderStageCreateInfo {
+                s_type: StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::PipelineShaderStageCreateFlags::empty(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                module: shader,
+                p_name: entry_point_name.as_ptr(),
+                p_specialization_info: if constants.is_empty() {
+                    ptr::null()
+                } else {
+                    &specialization_info
+                },
+            },
+            layout,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+        };
+
+        let pipeline = device.create_compute_pipelines(vk::PipelineCache::null(), &[info], None);
+        device.destroy_shader_module(shader, None);
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err((_, e)) => {
+                device.destroy_pipeline_layout(layout, None);
+                return Err(e.into());
+            }
+        };
+
+        Ok(Self {
+            pipeline: pipeline[0],
+            layout,
+            set_layout,
+            descriptor_set,
+            bindings: bindings.to_vec(),
+            buffers: vec![None; bindings.len()],
+            local_size,
+        })
+    }
+
+    /// Errors if `binding` is not a `kind` binding of the shader.
+    fn check_binding(&self, binding: u32, kind: ComputeBinding) -> Result<()> {
+        match self.bindings.get(binding as usize) {
+            Some(declared) if *declared == kind => Ok(()),
+            Some(declared) => Err(Error::msg(format!(
+                "binding {} is a {:?}, not a {:?}",
+                binding, declared, kind
+            ))),
+            None => Err(Error::msg(format!(
+                "binding {} out of {} bindings",
+                binding,
+                self.bindings.len()
+            ))),
+        }
+    }
+
+    /// Writes the pipeline's only descriptor set in place, so no dispatch of this pipeline may still be
+    /// pending, e.g. wait for the `ComputeJob` first. Dispatches recorded after see the new buffer.
+    pub unsafe fn bind_storage_buffer(
+        &mut self,
+        device: &ash::Device,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Result<()> {
+        self.check_binding(binding, ComputeBinding::StorageBuffer)?;
+        DescriptorWriter::new()
+            .write_buffer(binding, vk::DescriptorType::STORAGE_BUFFER, buffer, offset, range)
+            .update(device, self.descriptor_set);
+        self.buffers[binding as usize] = Some((buffer, offset, range));
+        Ok(())
+    }
+
+    /// Same rules as `bind_storage_buffer`. The image is not handed between families, with a dedicated
+    /// compute family it needs CONCURRENT sharing.
+    pub unsafe fn bind_storage_image(&self, device: &ash::Device, binding: u32, image_view: vk::ImageView) -> Result<()> {
+        self.check_binding(binding, ComputeBinding::StorageImage)?;
+        DescriptorWriter::new()
+            .write_image(
+                binding,
+                vk::DescriptorType::STORAGE_IMAGE,
+                image_view,
+                vk::ImageLayout::GENERAL,
+                vk::Sampler::null(),
+            )
+            .update(device, self.descriptor_set);
+        Ok(())
+    }
+
+    /// Records a dispatch covering `problem_size` invocations, the group count comes from the shader's local size.
+    pub unsafe fn dispatch(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, problem_size: [u32; 3]) {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        if self.descriptor_set != vk::DescriptorSet::null() {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+        }
+
+        let [x, y, z] = group_count(problem_size, self.local_size);
+        device.cmd_dispatch(command_buffer, x, y, z);
+    }
+
+    /// Queue family ownership transfers of every bound storage buffer.
+    fn buffer_transfers(
+        &self,
+        src_family: u32,
+        dst_family: u32,
+        src_access_mask: vk::AccessFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) -> Vec<vk::BufferMemoryBarrier> {
+        self.buffers
+            .iter()
+            .flatten()
+            .map(|&(buffer, offset, size)| vk::BufferMemoryBarrier {
+                s_type: StructureType::BUFFER_MEMORY_BARRIER,
+                p_next: ptr::null(),
+                src_access_mask,
+                dst_access_mask,
+                src_queue_family_index: src_family,
+                dst_queue_family_index: dst_family,
+                buffer,
+                offset,
+                size,
+            })
+            .collect()
+    }
+
+    /// The set layout and descriptor set go away with the cache and allocator they came from.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+    }
+}
+
+/// Queue compute jobs are submitted to, either the graphics family or a dedicated compute family.
+///
+/// Resources belong to the graphics family between jobs, the way `UploadContext` hands them over. With a
+/// dedicated compute family every job takes the bound storage buffers over from the graphics queue first
+/// and gives them back after the dispatch, each step chained to the next with a semaphore.
+pub struct ComputeContext {
+    pub queue: vk::Queue,
+    pub queue_family: u32,
+    command_pool: vk::CommandPool,
+    /// graphics queue and a pool of its family, None when compute runs on the graphics family
+    graphics: Option<(FamilyQueue, vk::CommandPool)>,
+}
+
+impl ComputeContext {
+    pub unsafe fn new(device: &ash::Device, compute: FamilyQueue, graphics: FamilyQueue) -> Result<Self> {
+        let command_pool = create_command_pool(device, &Some(compute.family))?;
+        let graphics = if compute.family != graphics.family {
+            match create_command_pool(device, &Some(graphics.family)) {
+                Ok(pool) => Some((graphics, pool)),
+                Err(e) => {
+                    device.destroy_command_pool(command_pool, None);
+                    return Err(e);
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Self {
+            queue: compute.queue,
+            queue_family: compute.family,
+            command_pool,
+            graphics,
+        })
+    }
+
+    /// Submits a single dispatch, waiting on `wait_semaphores` first.
+    /// The returned job signals both a fence for the cpu and a semaphore other submissions can chain on.
+    /// Graphics work using the storage buffers has to be submitted before the job, to be handed over with them.
+    pub unsafe fn run(
+        &self,
+        device: &ash::Device,
+        pipeline: &ComputePipeline,
+        problem_size: [u32; 3],
+        wait_semaphores: &[(vk::Semaphore, vk::PipelineStageFlags)],
+    ) -> Result<ComputeJob> {
+        let mut job = ComputeJob {
+            command_buffer: vk::CommandBuffer::null(),
+            command_pool: self.command_pool,
+            transfer: None,
+            fence: vk::Fence::null(),
+            finished: vk::Semaphore::null(),
+        };
+        if let Err(e) = self.submit(device, &mut job, pipeline, problem_size, wait_semaphores) {
+            job.free(device);
+            return Err(e.into());
+        }
+        Ok(job)
+    }
+
+    /// Records the job's command buffers, creates its sync objects and submits, storing each handle in
+    /// `job` as soon as it exists. When a submission fails after an earlier one went through, waits for
+    /// the queues so `job` can be freed.
+    unsafe fn submit(
+        &self,
+        device: &ash::Device,
+        job: &mut ComputeJob,
+        pipeline: &ComputePipeline,
+        problem_size: [u32; 3],
+        wait_semaphores: &[(vk::Semaphore, vk::PipelineStageFlags)],
+    ) -> VkResult<()> {
+        job.command_buffer = begin_one_time(device, self.command_pool)?;
+        let mut waits = wait_semaphores.to_vec();
+
+        if let Some((graphics, graphics_pool)) = self.graphics {
+            job.transfer = Some(JobTransfer {
+                graphics,
+                command_pool: graphics_pool,
+                release: vk::CommandBuffer::null(),
+                acquire: vk::CommandBuffer::null(),
+                to_compute: vk::Semaphore::null(),
+                to_graphics: vk::Semaphore::null(),
+            });
+            let transfer = job.transfer.as_mut().unwrap();
+
+            // earlier graphics work on the buffers is ordered before the release on the same queue
+            transfer.release = begin_one_time(device, graphics_pool)?;
+            let releases = pipeline.buffer_transfers(
+                graphics.family,
+                self.queue_family,
+                vk::AccessFlags::MEMORY_WRITE,
+                vk::AccessFlags::empty(),
+            );
+            device.cmd_pipeline_barrier(
+                transfer.release,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &releases,
+                &[],
+            );
+            device.end_command_buffer(transfer.release)?;
+
+            let acquires = pipeline.buffer_transfers(
+                graphics.family,
+                self.queue_family,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            );
+            device.cmd_pipeline_barrier(
+                job.command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &acquires,
+                &[],
+            );
+        }
+
+        pipeline.dispatch(device, job.command_buffer, problem_size);
+
+        // make the shader writes visible to the host and to copies reading the result back
+        let barrier = vk::MemoryBarrier {
+            s_type: StructureType::MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::SHADER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ | vk::AccessFlags::TRANSFER_READ,
+        };
+        device.cmd_pipeline_barrier(
+            job.command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::HOST | vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        );
+
+        if let Some(transfer) = job.transfer.as_mut() {
+            let graphics = transfer.graphics;
+            // and back to the graphics family, which reads the results
+            let releases = pipeline.buffer_transfers(
+                self.queue_family,
+                graphics.family,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::AccessFlags::empty(),
+            );
+            device.cmd_pipeline_barrier(
+                job.command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &releases,
+                &[],
+            );
+
+            transfer.acquire = begin_one_time(device, transfer.command_pool)?;
+            let acquires = pipeline.buffer_transfers(
+                self.queue_family,
+                graphics.family,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::MEMORY_READ,
+            );
+            device.cmd_pipeline_barrier(
+                transfer.acquire,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &acquires,
+                &[],
+            );
+            device.end_command_buffer(transfer.acquire)?;
+        }
+        device.end_command_buffer(job.command_buffer)?;
+
+        let fence_info = vk::FenceCreateInfo {
+            s_type: StructureType::FENCE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::FenceCreateFlags::empty(),
+        };
+        job.fence = device.create_fence(&fence_info, None)?;
+        job.finished = create_semaphore(device)?;
+        if let Some(transfer) = job.transfer.as_mut() {
+            transfer.to_compute = create_semaphore(device)?;
+            transfer.to_graphics = create_semaphore(device)?;
+        }
+
+        match &job.transfer {
+            None => submit(device, self.queue, job.command_buffer, &waits, job.finished, job.fence),
+            Some(transfer) => {
+                let graphics = transfer.graphics;
+                submit(
+                    device,
+                    graphics.queue,
+                    transfer.release,
+                    &[],
+                    transfer.to_compute,
+                    vk::Fence::null(),
+                )?;
+                waits.push((transfer.to_compute, vk::PipelineStageFlags::COMPUTE_SHADER));
+                let submitted = submit(
+                    device,
+                    self.queue,
+                    job.command_buffer,
+                    &waits,
+                    transfer.to_graphics,
+                    vk::Fence::null(),
+                )
+                .and_then(|_| {
+                    submit(
+                        device,
+                        graphics.queue,
+                        transfer.acquire,
+                        &[(transfer.to_graphics, vk::PipelineStageFlags::ALL_COMMANDS)],
+                        job.finished,
+                        job.fence,
+                    )
+                });
+                if submitted.is_err() {
+                    // part of the job is in flight, it has to finish before the caller frees the rest
+                    let _ = device.queue_wait_idle(graphics.queue);
+                    let _ = device.queue_wait_idle(self.queue);
+                }
+                submitted
+            }
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        device.destroy_command_pool(self.command_pool, None);
+        if let Some((_, pool)) = self.graphics {
+            device.destroy_command_pool(pool, None);
+        }
+    }
+}
+
+/// Graphics family half of a job on a dedicated compute family.
+struct JobTransfer {
+    graphics: FamilyQueue,
+    command_pool: vk::CommandPool,
+    /// hands the buffers to the compute family
+    release: vk::CommandBuffer,
+    /// takes them back after the dispatch
+    acquire: vk::CommandBuffer,
+    /// signaled by the release, waited on by the dispatch
+    to_compute: vk::Semaphore,
+    /// signaled by the dispatch, waited on by the acquire
+    to_graphics: vk::Semaphore,
+}
+
+/// A submitted dispatch, wait on it from the cpu or chain another submission on `finished`.
+pub struct ComputeJob {
+    command_buffer: vk::CommandBuffer,
+    command_pool: vk::CommandPool,
+    transfer: Option<JobTransfer>,
+    pub fence: vk::Fence,
+    /// signaled when the dispatch is done and the buffers are back with the graphics family
+    pub finished: vk::Semaphore,
+}
+
+impl ComputeJob {
+    pub unsafe fn is_done(&self, device: &ash::Device) -> VkResult<bool> {
+        device.get_fence_status(self.fence)
+    }
+
+    pub unsafe fn wait(&self, device: &ash::Device) -> VkResult<()> {
+        device.wait_for_fences(&[self.fence], true, u64::MAX)
+    }
+
+    /// Waits for the job and frees it, submissions chained on `finished` must be done with it as well.
+    pub unsafe fn destroy(self, device: &ash::Device) -> VkResult<()> {
+        self.wait(device)?;
+        self.free(device);
+        Ok(())
+    }
+
+    /// Frees whatever was created so far, nothing of it may be in flight.
+    unsafe fn free(self, device: &ash::Device) {
+        let free_command_buffer = |pool, command_buffer| {
+            if command_buffer != vk::CommandBuffer::null() {
+                device.free_command_buffers(pool, &[command_buffer]);
+            }
+        };
+        free_command_buffer(self.command_pool, self.command_buffer);
+        if let Some(transfer) = self.transfer {
+            free_command_buffer(transfer.command_pool, transfer.release);
+            free_command_buffer(transfer.command_pool, transfer.acquire);
+            device.destroy_semaphore(transfer.to_compute, None);
+            device.destroy_semaphore(transfer.to_graphics, None);
+        }
+        device.destroy_fence(self.fence, None);
+        device.destroy_semaphore(self.finished, None);
+    }
+}
+
+/// Allocates a primary command buffer from `pool` and begins it for one submission.
+unsafe fn begin_one_time(device: &ash::Device, pool: vk::CommandPool) -> VkResult<vk::CommandBuffer> {
+    let alloc_info = vk::CommandBufferAllocateInfo {
+        s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+        p_next: ptr::null(),
+        command_pool: pool,
+        level: vk::CommandBufferLevel::PRIMARY,
+        command_buffer_count: 1,
+    };
+    let command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
+    let begin_info = vk::CommandBufferBeginInfo {
+        s_type: StructureType::COMMAND_BUFFER_BEGIN_INFO,
+        p_next: ptr::null(),
+        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        p_inheritance_info: ptr::null(),
+    };
+    if let Err(e) = device.begin_command_buffer(command_buffer, &begin_info) {
+        device.free_command_buffers(pool, &[command_buffer]);
+        return Err(e);
+    }
+    Ok(command_buffer)
+}
+
+unsafe fn create_semaphore(device: &ash::Device) -> VkResult<vk::Semaphore> {
+    let semaphore_info = vk::SemaphoreCreateInfo {
+        s_type: StructureType::SEMAPHORE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::SemaphoreCreateFlags::empty(),
+    };
+    device.create_semaphore(&semaphore_info, None)
+}
+
+/// Submits one command buffer after `waits`, signaling `signal` and `fence`.
+unsafe fn submit(
+    device: &ash::Device,
+    queue: vk::Queue,
+    command_buffer: vk::CommandBuffer,
+    waits: &[(vk::Semaphore, vk::PipelineStageFlags)],
+    signal: vk::Semaphore,
+    fence: vk::Fence,
+) -> VkResult<()> {
+    let (wait_semaphores, wait_stages): (Vec<vk::Semaphore>, Vec<vk::PipelineStageFlags>) = waits.iter().copied().unzip();
+    let submit_info = vk::SubmitInfo {
+        s_type: StructureType::SUBMIT_INFO,
+        p_next: ptr::null(),
+        wait_semaphore_count: wait_semaphores.len() as u32,
+        p_wait_semaphores: wait_semaphores.as_ptr(),
+        p_wait_dst_stage_mask: wait_stages.as_ptr(),
+        command_buffer_count: 1,
+        p_command_buffers: &command_buffer,
+        signal_semaphore_count: 1,
+        p_signal_semaphores: &signal,
+    };
+    device.queue_submit(queue, &[submit_info], fence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::SpecializationValue;
+
+    /// spir-v bytes from `[opcode, operands..]` instructions, behind a minimal header
+    fn module(instructions: &[&[u32]]) -> Vec<u8> {
+        let mut words = vec![SPIRV_MAGIC, 0x0001_0000, 0, 100, 0];
+        for instruction in instructions {
+            words.push((instruction.len() as u32) << 16 | instruction[0]);
+            words.extend_from_slice(&instruction[1..]);
+        }
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn literal_local_size() {
+        let spirv = module(&[&[OP_EXECUTION_MODE, 1, EXECUTION_MODE_LOCAL_SIZE, 64, 2, 1]]);
+        assert_eq!(reflect_local_size(&spirv, &SpecializationConstants::new()), Some([64, 2, 1]));
+    }
+
+    #[test]
+    fn workgroup_size_from_spec_constants() {
+        // layout(local_size_x_id = 3, local_size_y = 4) in, as glslang writes it
+        let spirv = module(&[
+            &[OP_EXECUTION_MODE, 1, EXECUTION_MODE_LOCAL_SIZE, 1, 1, 1],
+            &[OP_DECORATE, 10, DECORATION_SPEC_ID, 3],
+            &[OP_DECORATE, 13, DECORATION_BUILT_IN, BUILT_IN_WORKGROUP_SIZE],
+            &[OP_SPEC_CONSTANT, 2, 10, 32],
+            &[OP_CONSTANT, 2, 11, 4],
+            &[OP_CONSTANT, 2, 12, 1],
+            &[OP_SPEC_CONSTANT_COMPOSITE, 5, 13, 10, 11, 12],
+        ]);
+        // the shader's default without a value
+        assert_eq!(reflect_local_size(&spirv, &SpecializationConstants::new()), Some([32, 4, 1]));
+
+        let constants = SpecializationConstants::new().with(3, SpecializationValue::UInt(256));
+        assert_eq!(reflect_local_size(&spirv, &constants), Some([256, 4, 1]));
+    }
+
+    #[test]
+    fn local_size_id() {
+        let spirv = module(&[
+            &[OP_EXECUTION_MODE_ID, 1, EXECUTION_MODE_LOCAL_SIZE_ID, 10, 11, 11],
+            &[OP_DECORATE, 10, DECORATION_SPEC_ID, 0],
+            &[OP_SPEC_CONSTANT, 2, 10, 8],
+            &[OP_CONSTANT, 2, 11, 2],
+        ]);
+        let constants = SpecializationConstants::new().with(0, SpecializationValue::UInt(16));
+        assert_eq!(reflect_local_size(&spirv, &constants), Some([16, 2, 2]));
+    }
+
+    #[test]
+    fn not_a_compute_module() {
+        let constants = SpecializationConstants::new();
+        assert_eq!(reflect_local_size(&[1, 2, 3], &constants), None);
+        assert_eq!(reflect_local_size(&[0; 20], &constants), None);
+        assert_eq!(reflect_local_size(&module(&[]), &constants), None);
+        // an unknown id for the size
+        let spirv = module(&[&[OP_EXECUTION_MODE_ID, 1, EXECUTION_MODE_LOCAL_SIZE_ID, 10, 10, 10]]);
+        assert_eq!(reflect_local_size(&spirv, &constants), None);
+    }
+
+    #[test]
+    fn group_count_rounds_up() {
+        assert_eq!(group_count([100, 1, 1], [64, 1, 1]), [2, 1, 1]);
+        assert_eq!(group_count([128, 30, 0], [64, 8, 1]), [2, 4, 0]);
+    }
+}
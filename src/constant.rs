@@ -25,6 +25,29 @@ pub mod support {
     pub const EXTENSION_SUPPORT_ARRAY_NAME: &[&'static CStr] = &[ash::extensions::khr::Swapchain::name()];
 }
 
+pub mod render {
+    use ash::vk;
+
+    /// use vulkan 1.3 dynamic rendering when the device supports it, render pass objects otherwise
+    pub const PREFER_DYNAMIC_RENDERING: bool = true;
+    /// near plane at depth 1 and far plane at 0
+    pub const REVERSE_Z: bool = false;
+    /// msaa sample count at startup, clamped to what the device supports. M cycles through the counts
+    pub const MSAA_SAMPLES: vk::SampleCountFlags = vk::SampleCountFlags::TYPE_4;
+    /// fraction of samples shaded individually, None keeps sample shading off
+    pub const SAMPLE_SHADING: Option<f32> = None;
+    /// global texture and buffer arrays through descriptor indexing, skipped when the device lacks it
+    pub const BINDLESS: bool = true;
+    pub const BINDLESS_MAX_TEXTURES: u32 = 4096;
+    pub const BINDLESS_MAX_BUFFERS: u32 = 1024;
+    /// sampled by the quads, registered in the bindless texture array or bound alone as set 1
+    pub const TEXTURE_PATH: &str = "statue-1275469_640.jpg";
+    /// `layout(constant_id)` of OPACITY in shader.frag
+    pub const OPACITY_CONSTANT_ID: u32 = 1;
+    /// alpha the overlay quad is blended with
+    pub const OVERLAY_OPACITY: f32 = 0.5;
+}
+
 pub mod Window_Info {
     pub const HEIGHT: u32 = 900;
     pub const WIDTH: u32 = 900;
@@ -38,15 +61,24 @@ lazy_static::lazy_static! {
 }
 
 #[repr(C)]
-pub struct Index(u16);
-
-#[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Vertex {
     pos: glm::Vector2<f32>,
     color: glm::Vector3<f32>,
+    normal: glm::Vector3<f32>,
+    uv: glm::Vector2<f32>,
 }
 
 impl Vertex {
+    pub const fn new(
+        pos: glm::Vector2<f32>,
+        color: glm::Vector3<f32>,
+        normal: glm::Vector3<f32>,
+        uv: glm::Vector2<f32>,
+    ) -> Self {
+        Self { pos, color, normal, uv }
+    }
+
     pub const fn get_binding_description() -> vk::VertexInputBindingDescription {
         vk::VertexInputBindingDescription {
             binding: 0,
@@ -55,7 +87,7 @@ impl Vertex {
         }
     }
 
-    pub const fn get_input_attribute_description() -> [vk::VertexInputAttributeDescription; 2] {
+    pub const fn get_input_attribute_description() -> [vk::VertexInputAttributeDescription; 4] {
         [
             vk::VertexInputAttributeDescription {
                 location: 0,
@@ -69,6 +101,18 @@ impl Vertex {
                 format: vk::Format::R32G32B32_SFLOAT,
                 offset: offset_of!(Vertex, color) as u32 as u32,
             },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Vertex, normal) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 3,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Vertex, uv) as u32,
+            },
         ]
     }
 }
@@ -77,19 +121,55 @@ pub const VERTICES: [Vertex; 4] = [
     Vertex {
         pos: glm::Vector2::<f32>::new(-0.5, -0.5),
         color: glm::Vector3::new(0.0, 0.0, 1.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(0.0, 0.0),
     },
     Vertex {
         pos: glm::Vector2::<f32>::new(0.5, -0.5),
         color: glm::Vector3::new(1.0, 0.0, 0.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(1.0, 0.0),
     },
     Vertex {
         pos: glm::Vector2::<f32>::new(0.5, 0.5),
         color: glm::Vector3::new(0.0, 1.0, 0.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(1.0, 1.0),
     },
     Vertex {
         pos: glm::Vector2::<f32>::new(-0.5, 0.5),
         color: glm::Vector3::new(0.0, 0.0, 1.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(0.0, 1.0),
+    },
+];
+
+/// Blended quad next to `VERTICES`, drawn after the opaque geometry.
+pub const OVERLAY_VERTICES: [Vertex; 4] = [
+    Vertex {
+        pos: glm::Vector2::<f32>::new(0.6, -0.4),
+        color: glm::Vector3::new(1.0, 1.0, 1.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(0.0, 0.0),
+    },
+    Vertex {
+        pos: glm::Vector2::<f32>::new(1.4, -0.4),
+        color: glm::Vector3::new(1.0, 1.0, 1.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(1.0, 0.0),
+    },
+    Vertex {
+        pos: glm::Vector2::<f32>::new(1.4, 0.4),
+        color: glm::Vector3::new(1.0, 1.0, 1.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(1.0, 1.0),
+    },
+    Vertex {
+        pos: glm::Vector2::<f32>::new(0.6, 0.4),
+        color: glm::Vector3::new(1.0, 1.0, 1.0),
+        normal: glm::Vector3::new(0.0, 0.0, 1.0),
+        uv: glm::Vector2::new(0.0, 1.0),
     },
 ];
 
-pub static INDICES: [Index; 6] = [Index(0), Index(1), Index(2), Index(2), Index(3), Index(0)];
+pub static INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
@@ -0,0 +1,89 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use ash::vk;
+
+use crate::{
+    allocator::{Allocation, MemoryAllocator},
+    bindless::BindlessSlot,
+};
+
+/// A resource waiting for the frames that may still use it to finish.
+pub enum Deletion {
+    Buffer(vk::Buffer, Allocation),
+    Image(vk::Image, Allocation),
+    ImageView(vk::ImageView),
+    /// a bindless array index, reused once no frame reads it anymore
+    BindlessSlot(BindlessSlot),
+}
+
+impl Deletion {
+    unsafe fn destroy(self, device: &ash::Device, allocator: &mut MemoryAllocator) {
+        match self {
+            Deletion::Buffer(buffer, allocation) => {
+                device.destroy_buffer(buffer, None);
+                allocator.free(device, allocation);
+            }
+            Deletion::Image(image, allocation) => {
+                device.destroy_image(image, None);
+                allocator.free(device, allocation);
+            }
+            Deletion::ImageView(view) => device.destroy_image_view(view, None),
+            Deletion::BindlessSlot(slot) => slot.release(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    frame: u64,
+    pending: VecDeque<(u64, Deletion)>,
+}
+
+/// Resources dropped during a frame, destroyed once every frame in flight that could have recorded
+/// them has finished. Clones share the same queue, resources keep one to push themselves on drop.
+#[derive(Clone, Default)]
+pub struct DeletionQueue(Rc<RefCell<Queue>>);
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, deletion: Deletion) {
+        let mut queue = self.0.borrow_mut();
+        let frame = queue.frame;
+        queue.pending.push_back((frame, deletion));
+    }
+
+    /// Starts the next frame, call after waiting on its fence. Destroys what was dropped
+    /// `frames_in_flight` or more frames ago.
+    pub unsafe fn next_frame(&self, device: &ash::Device, allocator: &mut MemoryAllocator, frames_in_flight: u64) {
+        let mut queue = self.0.borrow_mut();
+        queue.frame += 1;
+        let frame = queue.frame;
+        while queue
+            .pending
+            .front()
+            .is_some_and(|(dropped, _)| dropped + frames_in_flight <= frame)
+        {
+            let (_, deletion) = queue.pending.pop_front().unwrap();
+            deletion.destroy(device, allocator);
+        }
+    }
+
+    /// Destroys everything right away, the device has to be idle.
+    pub unsafe fn flush(&self, device: &ash::Device, allocator: &mut MemoryAllocator) {
+        let mut queue = self.0.borrow_mut();
+        for (_, deletion) in queue.pending.drain(..) {
+            deletion.destroy(device, allocator);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
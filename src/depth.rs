@@ -0,0 +1,177 @@
+use std::ptr;
+
+use anyhow::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, MemoryPropertyFlags, StructureType},
+};
+
+use crate::{
+    allocator::{Allocation, MemoryAllocator},
+    buffer::{create_image_view, image_create_info},
+    render_pass::has_stencil_component,
+};
+
+/// Depth test settings shared by the pipeline and the depth attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DepthConfig {
+    /// compare op for a regular 0 near, 1 far depth range
+    pub compare_op: vk::CompareOp,
+    /// near plane at 1 and far plane at 0, gives better float precision in the distance.
+    /// flips the compare op and the clear value, the projection has to match
+    pub reverse_z: bool,
+    pub write: bool,
+    /// picks a format with a stencil component
+    pub stencil: bool,
+}
+
+impl Default for DepthConfig {
+    fn default() -> Self {
+        Self {
+            compare_op: vk::CompareOp::LESS,
+            reverse_z: false,
+            write: true,
+            stencil: false,
+        }
+    }
+}
+
+impl DepthConfig {
+    /// The compare op the pipeline ends up using.
+    pub fn effective_compare_op(&self) -> vk::CompareOp {
+        if !self.reverse_z {
+            return self.compare_op;
+        }
+        match self.compare_op {
+            vk::CompareOp::LESS => vk::CompareOp::GREATER,
+            vk::CompareOp::LESS_OR_EQUAL => vk::CompareOp::GREATER_OR_EQUAL,
+            vk::CompareOp::GREATER => vk::CompareOp::LESS,
+            vk::CompareOp::GREATER_OR_EQUAL => vk::CompareOp::LESS_OR_EQUAL,
+            other => other,
+        }
+    }
+
+    /// Depth of the far plane.
+    pub fn clear_depth(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    pub fn clear_value(&self) -> vk::ClearValue {
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: self.clear_depth(),
+                stencil: 0,
+            },
+        }
+    }
+
+    pub fn depth_stencil_state(&self) -> vk::PipelineDepthStencilStateCreateInfo {
+        vk::PipelineDepthStencilStateCreateInfo {
+            s_type: StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
+            depth_test_enable: vk::TRUE,
+            depth_write_enable: self.write as vk::Bool32,
+            depth_compare_op: self.effective_compare_op(),
+            depth_bounds_test_enable: vk::FALSE,
+            stencil_test_enable: vk::FALSE,
+            front: vk::StencilOpState::default(),
+            back: vk::StencilOpState::default(),
+            min_depth_bounds: 0.0,
+            max_depth_bounds: 1.0,
+        }
+    }
+}
+
+/// Best depth format the device can use as an attachment, with a stencil component if asked for.
+pub unsafe fn find_depth_format(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    stencil: bool,
+) -> Result<vk::Format> {
+    let candidates: &[vk::Format] = if stencil {
+        &[
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::D16_UNORM_S8_UINT,
+        ]
+    } else {
+        &[
+            vk::Format::D32_SFLOAT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::X8_D24_UNORM_PACK32,
+            vk::Format::D16_UNORM,
+        ]
+    };
+
+    for format in candidates {
+        let properties = instance.get_physical_device_format_properties(physical_device, *format);
+        if properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        {
+            return Ok(*format);
+        }
+    }
+    Err(Error::msg("no supported depth format"))
+}
+
+/// Depth attachment sized to the swapchain, recreate it together with the swapchain.
+#[derive(Default)]
+pub struct DepthBuffer {
+    pub image: vk::Image,
+    pub allocation: Allocation,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+}
+
+impl DepthBuffer {
+    pub unsafe fn new(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> VkResult<Self> {
+        let image_info = image_create_info(
+            extent.width,
+            extent.height,
+            format,
+            samples,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        );
+        let (image, allocation) = allocator.create_image(device, &image_info, MemoryPropertyFlags::DEVICE_LOCAL)?;
+        let view = create_image_view(device, image, format, depth_aspect(format), 1)?;
+
+        Ok(Self {
+            image,
+            allocation,
+            view,
+            format,
+        })
+    }
+
+    pub fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        depth_aspect(self.format)
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device, allocator: &mut MemoryAllocator) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        allocator.free(device, self.allocation);
+    }
+}
+
+pub fn depth_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    if has_stencil_component(format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else {
+        vk::ImageAspectFlags::DEPTH
+    }
+}
@@ -0,0 +1,415 @@
+use std::{collections::HashMap, ptr};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+/// One binding of a descriptor set layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DescriptorBinding {
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// array size in the shader, 1 for a single descriptor
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+impl DescriptorBinding {
+    pub fn new(binding: u32, descriptor_type: vk::DescriptorType, stages: vk::ShaderStageFlags) -> Self {
+        Self {
+            binding,
+            descriptor_type,
+            count: 1,
+            stages,
+        }
+    }
+
+    pub fn with_count(mut self, count: u32) -> Self {
+        self.count = count;
+        self
+    }
+
+    fn layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding: self.binding,
+            descriptor_type: self.descriptor_type,
+            descriptor_count: self.count,
+            stage_flags: self.stages,
+            p_immutable_samplers: ptr::null(),
+        }
+    }
+}
+
+/// Set layouts keyed by their bindings, the same bindings always give back the same layout.
+#[derive(Default)]
+pub struct DescriptorLayoutCache {
+    layouts: HashMap<Vec<DescriptorBinding>, vk::DescriptorSetLayout>,
+}
+
+impl DescriptorLayoutCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The layout is owned by the cache, do not destroy it yourself.
+    pub unsafe fn get(&mut self, device: &ash::Device, bindings: &[DescriptorBinding]) -> VkResult<vk::DescriptorSetLayout> {
+        // binding order does not matter to vulkan, so it should not matter to the cache either
+        let mut key = bindings.to_vec();
+        key.sort_by_key(|binding| binding.binding);
+        if let Some(layout) = self.layouts.get(&key) {
+            return Ok(*layout);
+        }
+
+        let layout_bindings: Vec<vk::DescriptorSetLayoutBinding> =
+            key.iter().map(|binding| binding.layout_binding()).collect();
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: layout_bindings.len() as u32,
+            p_bindings: layout_bindings.as_ptr(),
+        };
+        let layout = device.create_descriptor_set_layout(&layout_info, None)?;
+        self.layouts.insert(key, layout);
+        Ok(layout)
+    }
+
+    /// Bindings `layout` was created with, None if it did not come from this cache.
+    pub fn bindings(&self, layout: vk::DescriptorSetLayout) -> Option<&[DescriptorBinding]> {
+        self.layouts
+            .iter()
+            .find(|(_, cached)| **cached == layout)
+            .map(|(bindings, _)| bindings.as_slice())
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for (_, layout) in self.layouts.drain() {
+            device.destroy_descriptor_set_layout(layout, None);
+        }
+    }
+}
+
+/// Descriptors of each type reserved per set when a pool is created, covers every core descriptor type.
+pub const DEFAULT_POOL_RATIOS: [(vk::DescriptorType, f32); 11] = [
+    (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+    (vk::DescriptorType::STORAGE_BUFFER, 2.0),
+    (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 0.5),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+    (vk::DescriptorType::SAMPLED_IMAGE, 2.0),
+    (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+    (vk::DescriptorType::SAMPLER, 1.0),
+    (vk::DescriptorType::INPUT_ATTACHMENT, 1.0),
+    (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 0.5),
+    (vk::DescriptorType::STORAGE_TEXEL_BUFFER, 0.5),
+];
+
+/// Upper bound of sets in one pool, pools stop growing there.
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// Hands out descriptor sets and creates another, bigger, pool when the current one runs out.
+/// Sets are not freed one by one, `reset` recycles all of them at once.
+pub struct DescriptorAllocator {
+    ratios: Vec<(vk::DescriptorType, f32)>,
+    sets_per_pool: u32,
+    /// pool sets are allocated from, the last one of `full_pools` otherwise
+    current: Option<vk::DescriptorPool>,
+    full_pools: Vec<vk::DescriptorPool>,
+    /// reset pools waiting to be reused
+    free_pools: Vec<vk::DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(sets_per_pool: u32) -> Self {
+        Self::with_ratios(sets_per_pool, &DEFAULT_POOL_RATIOS)
+    }
+
+    /// `ratios` is the number of descriptors of a type reserved per set.
+    pub fn with_ratios(sets_per_pool: u32, ratios: &[(vk::DescriptorType, f32)]) -> Self {
+        Self {
+            ratios: ratios.to_vec(),
+            sets_per_pool: sets_per_pool.max(1),
+            current: None,
+            full_pools: vec![],
+            free_pools: vec![],
+        }
+    }
+
+    /// `layouts` is the cache `layout` came from, a new pool is made big enough for it when the current one
+    /// runs out.
+    pub unsafe fn allocate(
+        &mut self,
+        device: &ash::Device,
+        layouts: &DescriptorLayoutCache,
+        layout: vk::DescriptorSetLayout,
+    ) -> VkResult<vk::DescriptorSet> {
+        let pool = self.current_pool(device)?;
+        match allocate_set(device, pool, layout) {
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                // retire the pool and try once more with a fresh one that holds at least this layout
+                self.full_pools.push(pool);
+                let bindings = layouts.bindings(layout).unwrap_or(&[]);
+                let pool = create_pool(device, self.sets_per_pool, &self.ratios, bindings)?;
+                self.sets_per_pool = (self.sets_per_pool + self.sets_per_pool / 2).min(MAX_SETS_PER_POOL);
+                self.current = Some(pool);
+                allocate_set(device, pool, layout)
+            }
+            result => result,
+        }
+    }
+
+    /// Frees every set handed out so far, none of them may still be in use by the gpu.
+    pub unsafe fn reset(&mut self, device: &ash::Device) -> VkResult<()> {
+        for pool in self.full_pools.drain(..).chain(self.current.take()) {
+            device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?;
+            self.free_pools.push(pool);
+        }
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for pool in self
+            .full_pools
+            .drain(..)
+            .chain(self.free_pools.drain(..))
+            .chain(self.current.take())
+        {
+            device.destroy_descriptor_pool(pool, None);
+        }
+    }
+
+    unsafe fn current_pool(&mut self, device: &ash::Device) -> VkResult<vk::DescriptorPool> {
+        if let Some(pool) = self.current {
+            return Ok(pool);
+        }
+
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => {
+                let pool = create_pool(device, self.sets_per_pool, &self.ratios, &[])?;
+                // the next pool gets bigger, whoever exhausted this one will likely do it again
+                self.sets_per_pool = (self.sets_per_pool + self.sets_per_pool / 2).min(MAX_SETS_PER_POOL);
+                pool
+            }
+        };
+        self.current = Some(pool);
+        Ok(pool)
+    }
+}
+
+/// Pool with `ratios` descriptors per set, and at least the descriptors of one set with `bindings`.
+unsafe fn create_pool(
+    device: &ash::Device,
+    max_sets: u32,
+    ratios: &[(vk::DescriptorType, f32)],
+    bindings: &[DescriptorBinding],
+) -> VkResult<vk::DescriptorPool> {
+    let mut pool_sizes: Vec<vk::DescriptorPoolSize> = ratios
+        .iter()
+        .map(|(ty, ratio)| vk::DescriptorPoolSize {
+            ty: *ty,
+            descriptor_count: ((max_sets as f32 * ratio) as u32).max(1),
+        })
+        .collect();
+    for binding in bindings {
+        let needed: u32 = bindings
+            .iter()
+            .filter(|other| other.descriptor_type == binding.descriptor_type)
+            .map(|other| other.count)
+            .sum();
+        match pool_sizes.iter_mut().find(|size| size.ty == binding.descriptor_type) {
+            Some(size) => size.descriptor_count = size.descriptor_count.max(needed),
+            None => pool_sizes.push(vk::DescriptorPoolSize {
+                ty: binding.descriptor_type,
+                descriptor_count: needed,
+            }),
+        }
+    }
+    let pool_info = vk::DescriptorPoolCreateInfo {
+        s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DescriptorPoolCreateFlags::empty(),
+        max_sets,
+        pool_size_count: pool_sizes.len() as u32,
+        p_pool_sizes: pool_sizes.as_ptr(),
+    };
+    device.create_descriptor_pool(&pool_info, None)
+}
+
+unsafe fn allocate_set(
+    device: &ash::Device,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+) -> VkResult<vk::DescriptorSet> {
+    let alloc_info = vk::DescriptorSetAllocateInfo {
+        s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+        p_next: ptr::null(),
+        descriptor_pool: pool,
+        descriptor_set_count: 1,
+        p_set_layouts: &layout,
+    };
+    Ok(device.allocate_descriptor_sets(&alloc_info)?[0])
+}
+
+/// One allocator per frame in flight, a frame's sets are recycled once its fence has been waited on.
+pub struct FrameDescriptors {
+    allocators: Vec<DescriptorAllocator>,
+}
+
+impl FrameDescriptors {
+    pub fn new(frames_in_flight: usize, sets_per_pool: u32) -> Self {
+        Self {
+            allocators: (0..frames_in_flight)
+                .map(|_| DescriptorAllocator::new(sets_per_pool))
+                .collect(),
+        }
+    }
+
+    pub fn frame(&mut self, frame: usize) -> &mut DescriptorAllocator {
+        &mut self.allocators[frame]
+    }
+
+    /// Call at the start of `frame`, after waiting for its fence.
+    pub unsafe fn begin_frame(&mut self, device: &ash::Device, frame: usize) -> VkResult<&mut DescriptorAllocator> {
+        self.allocators[frame].reset(device)?;
+        Ok(&mut self.allocators[frame])
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for allocator in self.allocators.iter_mut() {
+            allocator.destroy(device);
+        }
+    }
+}
+
+enum PendingInfo {
+    Buffer(usize),
+    Image(usize),
+}
+
+/// Collects descriptor writes and applies them with a single `update_descriptor_sets`.
+#[derive(Default)]
+pub struct DescriptorWriter {
+    buffer_infos: Vec<vk::DescriptorBufferInfo>,
+    image_infos: Vec<vk::DescriptorImageInfo>,
+    /// (binding, array element, type, info)
+    writes: Vec<(u32, u32, vk::DescriptorType, PendingInfo)>,
+}
+
+impl DescriptorWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uniform or storage buffer, `range` can be `vk::WHOLE_SIZE`.
+    pub fn write_buffer(
+        &mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> &mut Self {
+        self.write_buffer_element(binding, 0, descriptor_type, buffer, offset, range)
+    }
+
+    /// Like `write_buffer`, into element `array_element` of an arrayed binding.
+    pub fn write_buffer_element(
+        &mut self,
+        binding: u32,
+        array_element: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> &mut Self {
+        self.buffer_infos.push(vk::DescriptorBufferInfo { buffer, offset, range });
+        self.writes.push((
+            binding,
+            array_element,
+            descriptor_type,
+            PendingInfo::Buffer(self.buffer_infos.len() - 1),
+        ));
+        self
+    }
+
+    /// Sampled, storage or combined image, `sampler` is ignored for types that do not take one.
+    pub fn write_image(
+        &mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        image_view: vk::ImageView,
+        image_layout: vk::ImageLayout,
+        sampler: vk::Sampler,
+    ) -> &mut Self {
+        self.write_image_element(binding, 0, descriptor_type, image_view, image_layout, sampler)
+    }
+
+    /// Like `write_image`, into element `array_element` of an arrayed binding.
+    pub fn write_image_element(
+        &mut self,
+        binding: u32,
+        array_element: u32,
+        descriptor_type: vk::DescriptorType,
+        image_view: vk::ImageView,
+        image_layout: vk::ImageLayout,
+        sampler: vk::Sampler,
+    ) -> &mut Self {
+        self.image_infos.push(vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout,
+        });
+        self.writes.push((
+            binding,
+            array_element,
+            descriptor_type,
+            PendingInfo::Image(self.image_infos.len() - 1),
+        ));
+        self
+    }
+
+    pub fn write_sampler(&mut self, binding: u32, sampler: vk::Sampler) -> &mut Self {
+        self.write_image(
+            binding,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            vk::ImageLayout::UNDEFINED,
+            sampler,
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer_infos.clear();
+        self.image_infos.clear();
+        self.writes.clear();
+    }
+
+    pub unsafe fn update(&self, device: &ash::Device, set: vk::DescriptorSet) {
+        // the infos are only pointed to here, pushing to the vecs earlier could have moved them
+        let writes: Vec<vk::WriteDescriptorSet> = self
+            .writes
+            .iter()
+            .map(|(binding, array_element, descriptor_type, info)| {
+                let (p_buffer_info, p_image_info) = match info {
+                    PendingInfo::Buffer(index) => (&self.buffer_infos[*index] as *const _, ptr::null()),
+                    PendingInfo::Image(index) => (ptr::null(), &self.image_infos[*index] as *const _),
+                };
+                vk::WriteDescriptorSet {
+                    s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                    p_next: ptr::null(),
+                    dst_set: set,
+                    dst_binding: *binding,
+                    dst_array_element: *array_element,
+                    descriptor_count: 1,
+                    descriptor_type: *descriptor_type,
+                    p_image_info,
+                    p_buffer_info,
+                    p_texel_buffer_view: ptr::null(),
+                }
+            })
+            .collect();
+        device.update_descriptor_sets(&writes, &[]);
+    }
+}
@@ -1,5 +1,5 @@
 use std::collections::HashSet;
-use std::ffi::CStr;
+use std::ffi::{c_void, CStr};
 use std::os::raw::c_char;
 use std::ptr;
 
@@ -13,6 +13,8 @@ use crate::SwapChainSupportDetails;
 
 use crate::{constant::support, utility, QueueFamilyIndices};
 
+const MEMORY_BUDGET_EXTENSION: &CStr = vk::ExtMemoryBudgetFn::name();
+
 unsafe fn is_device_suitable(
     physical_device: vk::PhysicalDevice,
     instance: &ash::Instance,
@@ -107,12 +109,108 @@ pub unsafe fn pick_physical_device(
     Err(Error::msg("No Vulkan Supported GPU"))
 }
 
+/// Optional device features, request them at device creation and check what actually got enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceFeatures {
+    /// `cmd_begin_rendering` without render pass and framebuffer objects (vulkan 1.3)
+    pub dynamic_rendering: bool,
+    /// per sample shading for msaa
+    pub sample_rate_shading: bool,
+    /// anisotropic texture filtering
+    pub sampler_anisotropy: bool,
+    /// line and point polygon modes, used by the wireframe and point render modes
+    pub fill_mode_non_solid: bool,
+    /// runtime sized, partially bound and update after bind descriptor arrays for bindless resources (vulkan 1.2)
+    pub descriptor_indexing: bool,
+    /// VK_EXT_memory_budget, lets the allocator see how much of each heap is left
+    pub memory_budget: bool,
+    /// semaphores counting up a 64 bit value, used to track async uploads (vulkan 1.2)
+    pub timeline_semaphore: bool,
+    /// 64 bit buffer addresses shaders can use as pointers (vulkan 1.2)
+    pub buffer_device_address: bool,
+}
+
+impl DeviceFeatures {
+    /// Everything this crate knows about that the device supports.
+    pub unsafe fn supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let is_vulkan_12 = properties.api_version >= vk::API_VERSION_1_2;
+        let is_vulkan_13 = properties.api_version >= vk::API_VERSION_1_3;
+
+        // only chain the structs the device version knows about
+        let mut features_12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut features_13 = vk::PhysicalDeviceVulkan13Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default();
+        if is_vulkan_12 {
+            features.p_next = &mut features_12 as *mut vk::PhysicalDeviceVulkan12Features as *mut c_void;
+        }
+        if is_vulkan_13 {
+            features_12.p_next = &mut features_13 as *mut vk::PhysicalDeviceVulkan13Features as *mut c_void;
+        }
+        instance.get_physical_device_features2(physical_device, &mut features);
+
+        let descriptor_indexing = [
+            features_12.descriptor_indexing,
+            features_12.runtime_descriptor_array,
+            features_12.descriptor_binding_partially_bound,
+            features_12.descriptor_binding_variable_descriptor_count,
+            features_12.descriptor_binding_update_unused_while_pending,
+            features_12.descriptor_binding_sampled_image_update_after_bind,
+            features_12.descriptor_binding_storage_buffer_update_after_bind,
+            features_12.shader_sampled_image_array_non_uniform_indexing,
+            features_12.shader_storage_buffer_array_non_uniform_indexing,
+        ]
+        .iter()
+        .all(|feature| *feature == vk::TRUE);
+
+        let memory_budget = instance
+            .enumerate_device_extension_properties(physical_device)
+            .unwrap_or_default()
+            .iter()
+            .any(|extension| utility::vk_to_string(&extension.extension_name) == MEMORY_BUDGET_EXTENSION.to_str().unwrap());
+
+        Self {
+            dynamic_rendering: is_vulkan_13 && features_13.dynamic_rendering == vk::TRUE,
+            sample_rate_shading: features.features.sample_rate_shading == vk::TRUE,
+            sampler_anisotropy: features.features.sampler_anisotropy == vk::TRUE,
+            fill_mode_non_solid: features.features.fill_mode_non_solid == vk::TRUE,
+            descriptor_indexing: is_vulkan_12 && descriptor_indexing,
+            memory_budget,
+            timeline_semaphore: is_vulkan_12 && features_12.timeline_semaphore == vk::TRUE,
+            buffer_device_address: is_vulkan_12 && features_12.buffer_device_address == vk::TRUE,
+        }
+    }
+
+    /// Features that are both requested and supported.
+    pub fn intersect(&self, other: &DeviceFeatures) -> Self {
+        Self {
+            dynamic_rendering: self.dynamic_rendering && other.dynamic_rendering,
+            sample_rate_shading: self.sample_rate_shading && other.sample_rate_shading,
+            sampler_anisotropy: self.sampler_anisotropy && other.sampler_anisotropy,
+            fill_mode_non_solid: self.fill_mode_non_solid && other.fill_mode_non_solid,
+            descriptor_indexing: self.descriptor_indexing && other.descriptor_indexing,
+            memory_budget: self.memory_budget && other.memory_budget,
+            timeline_semaphore: self.timeline_semaphore && other.timeline_semaphore,
+            buffer_device_address: self.buffer_device_address && other.buffer_device_address,
+        }
+    }
+
+    fn needs_vulkan_12(&self) -> bool {
+        self.descriptor_indexing || self.timeline_semaphore || self.buffer_device_address
+    }
+
+    fn needs_vulkan_13(&self) -> bool {
+        self.dynamic_rendering
+    }
+}
+
 pub unsafe fn create_logical_device(
     physical_device: vk::PhysicalDevice,
     instance: &ash::Instance,
     surface: vk::SurfaceKHR,
     surface_loader: &ash::extensions::khr::Surface,
-) -> Result<(ash::Device, QueueFamilyIndices)> {
+    requested_features: &DeviceFeatures,
+) -> Result<(ash::Device, QueueFamilyIndices, DeviceFeatures)> {
     let queue_priorities = [1.0];
     let indices = QueueFamilyIndices::find_queue_family(physical_device, instance, &surface_loader, &surface)?;
     // Create the queue info with the correct queue priorities
@@ -123,6 +221,7 @@ pub unsafe fn create_logical_device(
     unique_queue.insert(indices.graphics_family);
     unique_queue.insert(indices.present_family);
     unique_queue.insert(indices.transfer_family);
+    unique_queue.insert(indices.compute_family);
 
     for queue_index in unique_queue.iter() {
         let queue_info = vk::DeviceQueueCreateInfo {
@@ -136,17 +235,55 @@ pub unsafe fn create_logical_device(
         queues_infos.push(queue_info);
     }
 
-    let feature_info = vk::PhysicalDeviceFeatures::default();
+    let enabled_features = requested_features.intersect(&DeviceFeatures::supported(instance, physical_device));
+
+    let mut features_12 = vk::PhysicalDeviceVulkan12Features::default();
+    if enabled_features.descriptor_indexing {
+        features_12.descriptor_indexing = vk::TRUE;
+        features_12.runtime_descriptor_array = vk::TRUE;
+        features_12.descriptor_binding_partially_bound = vk::TRUE;
+        features_12.descriptor_binding_variable_descriptor_count = vk::TRUE;
+        features_12.descriptor_binding_update_unused_while_pending = vk::TRUE;
+        features_12.descriptor_binding_sampled_image_update_after_bind = vk::TRUE;
+        features_12.descriptor_binding_storage_buffer_update_after_bind = vk::TRUE;
+        features_12.shader_sampled_image_array_non_uniform_indexing = vk::TRUE;
+        features_12.shader_storage_buffer_array_non_uniform_indexing = vk::TRUE;
+    }
+    features_12.timeline_semaphore = enabled_features.timeline_semaphore as vk::Bool32;
+    features_12.buffer_device_address = enabled_features.buffer_device_address as vk::Bool32;
+
+    let mut features_13 = vk::PhysicalDeviceVulkan13Features::default();
+    features_13.dynamic_rendering = enabled_features.dynamic_rendering as vk::Bool32;
+
+    let mut feature_info = vk::PhysicalDeviceFeatures2::default();
+    feature_info.features.sample_rate_shading = enabled_features.sample_rate_shading as vk::Bool32;
+    feature_info.features.sampler_anisotropy = enabled_features.sampler_anisotropy as vk::Bool32;
+    feature_info.features.fill_mode_non_solid = enabled_features.fill_mode_non_solid as vk::Bool32;
+
+    // chain only what is used, older devices do not know the newer structs
+    let mut next: *mut c_void = ptr::null_mut();
+    if enabled_features.needs_vulkan_13() {
+        features_13.p_next = next;
+        next = &mut features_13 as *mut vk::PhysicalDeviceVulkan13Features as *mut c_void;
+    }
+    if enabled_features.needs_vulkan_12() {
+        features_12.p_next = next;
+        next = &mut features_12 as *mut vk::PhysicalDeviceVulkan12Features as *mut c_void;
+    }
+    feature_info.p_next = next;
 
     let mut extension_names = vec![];
     for extension_required in constant::support::EXTENSION_SUPPORT_ARRAY_BYTES {
         extension_names.push(CStr::from_bytes_with_nul_unchecked(*extension_required));
     }
+    if enabled_features.memory_budget {
+        extension_names.push(MEMORY_BUDGET_EXTENSION);
+    }
     let extension_names_raw: Vec<*const c_char> = extension_names.iter().map(|raw_name| raw_name.as_ptr()).collect();
 
     let device_info = vk::DeviceCreateInfo {
         s_type: vk::StructureType::DEVICE_CREATE_INFO,
-        p_next: ptr::null(),
+        p_next: &feature_info as *const vk::PhysicalDeviceFeatures2 as *const c_void,
         flags: vk::DeviceCreateFlags::empty(),
         queue_create_info_count: queues_infos.len() as u32,
         p_queue_create_infos: queues_infos.as_ptr(),
@@ -154,11 +291,11 @@ pub unsafe fn create_logical_device(
         pp_enabled_layer_names: ptr::null(),
         enabled_extension_count: extension_names_raw.len() as u32,
         pp_enabled_extension_names: extension_names_raw.as_ptr(),
-        p_enabled_features: &feature_info,
+        p_enabled_features: ptr::null(),
     };
 
     let device = instance.create_device(physical_device, &device_info, None)?;
-    Ok((device, indices))
+    Ok((device, indices, enabled_features))
 }
 
 pub fn get_version_api(api: u32) -> (u32, u32, u32, u32) {
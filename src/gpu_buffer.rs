@@ -0,0 +1,212 @@
+use std::{
+    marker::PhantomData,
+    mem::size_of,
+    ptr::{self, NonNull},
+};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, MemoryPropertyFlags, StructureType},
+};
+
+use crate::{
+    allocator::{Allocation, MemoryAllocator},
+    deletion::{Deletion, DeletionQueue},
+    memory::MemoryProperties,
+    upload::UploadContext,
+};
+
+/// A buffer of `len` elements of `T` that owns its memory.
+///
+/// Dropping it hands the buffer to the deletion queue, which destroys it once no frame in flight uses it.
+pub struct Buffer<T: Copy> {
+    handle: vk::Buffer,
+    allocation: Allocation,
+    len: usize,
+    usage: vk::BufferUsageFlags,
+    memory_flags: MemoryPropertyFlags,
+    device_address: Option<vk::DeviceAddress>,
+    deletion: DeletionQueue,
+    marker: PhantomData<T>,
+}
+
+impl<T: Copy> Buffer<T> {
+    /// Uninitialized buffer with room for `len` elements. Add SHADER_DEVICE_ADDRESS to `usage` to get a
+    /// `device_address`, that needs the `buffer_device_address` feature.
+    pub unsafe fn new(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        properties: impl Into<MemoryProperties>,
+    ) -> VkResult<Self> {
+        let size = (len.max(1) * size_of::<T>()) as vk::DeviceSize;
+        let (handle, allocation) = allocator.create_buffer(device, size, usage, properties)?;
+        let memory_flags = allocator.memory_properties.memory_types[allocation.memory_type as usize].property_flags;
+        let device_address = usage
+            .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+            .then(|| buffer_device_address(device, handle));
+
+        Ok(Self {
+            handle,
+            allocation,
+            len,
+            usage,
+            memory_flags,
+            device_address,
+            deletion: deletion.clone(),
+            marker: PhantomData,
+        })
+    }
+
+    /// Device local buffer filled with `data` through `uploads`, usable once the upload ticket completed.
+    pub unsafe fn with_data(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+    ) -> VkResult<Self> {
+        let buffer = Self::new(
+            device,
+            allocator,
+            deletion,
+            data.len(),
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        uploads.upload_buffer(device, data, buffer.handle, 0)?;
+        Ok(buffer)
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        (self.len * size_of::<T>()) as vk::DeviceSize
+    }
+
+    pub fn usage(&self) -> vk::BufferUsageFlags {
+        self.usage
+    }
+
+    /// Address of the first element for shaders, None without SHADER_DEVICE_ADDRESS usage.
+    pub fn device_address(&self) -> Option<vk::DeviceAddress> {
+        self.device_address
+    }
+
+    pub fn is_host_visible(&self) -> bool {
+        self.memory_flags.contains(MemoryPropertyFlags::HOST_VISIBLE)
+    }
+
+    /// Host pointer to the first element, stays valid for the lifetime of the buffer.
+    /// None for device only memory.
+    pub fn mapped(&self) -> Option<NonNull<T>> {
+        self.allocation.mapped.map(NonNull::cast)
+    }
+
+    /// Copies `data` to element `first` onwards and flushes it, the memory has to be host visible and the
+    /// gpu must not use that range right now. Fails with ERROR_MEMORY_MAP_FAILED for device only memory.
+    pub unsafe fn write(
+        &mut self,
+        device: &ash::Device,
+        allocator: &MemoryAllocator,
+        first: usize,
+        data: &[T],
+    ) -> VkResult<()> {
+        assert!(first + data.len() <= self.len, "write past the end of the buffer");
+        let pointer = self.mapped().ok_or(vk::Result::ERROR_MEMORY_MAP_FAILED)?;
+        pointer
+            .as_ptr()
+            .add(first)
+            .copy_from_nonoverlapping(data.as_ptr(), data.len());
+        self.flush(device, allocator, first, data.len())
+    }
+
+    /// Reads `out.len()` elements starting at `first`, the gpu has to be done writing them.
+    pub unsafe fn read(
+        &self,
+        device: &ash::Device,
+        allocator: &MemoryAllocator,
+        first: usize,
+        out: &mut [T],
+    ) -> VkResult<()> {
+        assert!(first + out.len() <= self.len, "read past the end of the buffer");
+        let pointer = self.mapped().ok_or(vk::Result::ERROR_MEMORY_MAP_FAILED)?;
+        self.invalidate(device, allocator, first, out.len())?;
+        out.as_mut_ptr()
+            .copy_from_nonoverlapping(pointer.as_ptr().add(first), out.len());
+        Ok(())
+    }
+
+    pub unsafe fn read_to_vec(&self, device: &ash::Device, allocator: &MemoryAllocator) -> VkResult<Vec<T>> {
+        let pointer = self.mapped().ok_or(vk::Result::ERROR_MEMORY_MAP_FAILED)?;
+        self.invalidate(device, allocator, 0, self.len)?;
+        Ok(std::slice::from_raw_parts(pointer.as_ptr(), self.len).to_vec())
+    }
+
+    /// Makes host writes to `count` elements from `first` visible to the gpu, only needed after writing
+    /// through `mapped` to memory that is not host coherent.
+    pub unsafe fn flush(
+        &self,
+        device: &ash::Device,
+        allocator: &MemoryAllocator,
+        first: usize,
+        count: usize,
+    ) -> VkResult<()> {
+        let (offset, size) = Self::byte_range(first, count);
+        allocator.flush(device, &self.allocation, offset, size)
+    }
+
+    /// Makes gpu writes to `count` elements from `first` visible to the host, only needed before reading
+    /// through `mapped` from memory that is not host coherent.
+    pub unsafe fn invalidate(
+        &self,
+        device: &ash::Device,
+        allocator: &MemoryAllocator,
+        first: usize,
+        count: usize,
+    ) -> VkResult<()> {
+        let (offset, size) = Self::byte_range(first, count);
+        allocator.invalidate(device, &self.allocation, offset, size)
+    }
+
+    fn byte_range(first: usize, count: usize) -> (vk::DeviceSize, vk::DeviceSize) {
+        (
+            (first * size_of::<T>()) as vk::DeviceSize,
+            (count * size_of::<T>()) as vk::DeviceSize,
+        )
+    }
+}
+
+/// 64 bit address of `buffer`, it needs SHADER_DEVICE_ADDRESS usage and memory allocated with the
+/// device address flag.
+pub unsafe fn buffer_device_address(device: &ash::Device, buffer: vk::Buffer) -> vk::DeviceAddress {
+    let info = vk::BufferDeviceAddressInfo {
+        s_type: StructureType::BUFFER_DEVICE_ADDRESS_INFO,
+        p_next: ptr::null(),
+        buffer,
+    };
+    device.get_buffer_device_address(&info)
+}
+
+impl<T: Copy> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        self.deletion.push(Deletion::Buffer(self.handle, self.allocation));
+    }
+}
@@ -5,17 +5,48 @@ use ash::{
     vk::{self, QueueFlags},
 };
 
+pub mod allocator;
+pub mod bindless;
+pub mod blend;
 pub mod buffer;
+pub mod compute;
 pub mod constant;
+pub mod deletion;
+pub mod depth;
+pub mod descriptor;
 pub mod device;
+pub mod gpu_buffer;
+pub mod memory;
+pub mod mesh;
+pub mod mipmap;
+pub mod msaa;
 pub mod pipeline;
 pub mod platform;
+pub mod push_constant;
+pub mod readback;
+pub mod render_mode;
+pub mod render_pass;
+pub mod render_queue;
+pub mod rendering;
+pub mod sampler;
+pub mod texture;
+pub mod uniform;
+pub mod upload;
 pub mod utility;
 
+/// A queue together with the family it was taken from.
+#[derive(Clone, Copy, Debug)]
+pub struct FamilyQueue {
+    pub family: u32,
+    pub queue: vk::Queue,
+}
+
 pub struct QueueFamilyIndices {
     pub graphics_family: Option<u32>,
     pub present_family: Option<u32>,
     pub transfer_family: Option<u32>,
+    /// dedicated compute family if there is one, otherwise the graphics family
+    pub compute_family: Option<u32>,
 }
 
 impl QueueFamilyIndices {
@@ -39,6 +70,7 @@ impl QueueFamilyIndices {
             graphics_family: None,
             present_family: None,
             transfer_family: None,
+            compute_family: None,
         };
 
         let mut queue_families = vec![ash::vk::QueueFamilyProperties2::default(); queue_count];
@@ -63,11 +95,23 @@ impl QueueFamilyIndices {
             }
 
             if queue_family_ret.is_completed() {
+                queue_family_ret.compute_family =
+                    Self::find_compute_family(&queue_families).or(queue_family_ret.graphics_family);
                 return Ok(queue_family_ret);
             }
         }
         return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
     }
+
+    fn find_compute_family(queue_families: &[vk::QueueFamilyProperties2]) -> Option<u32> {
+        queue_families
+            .iter()
+            .position(|queue| {
+                queue.queue_family_properties.queue_flags & (QueueFlags::COMPUTE | QueueFlags::GRAPHICS)
+                    == QueueFlags::COMPUTE
+            })
+            .map(|index| index as u32)
+    }
 }
 
 // Basic surface capabilities (min/max number of images in swap chain, min/max width and height of images)
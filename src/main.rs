@@ -5,29 +5,48 @@ use ash::{
     vk::{self, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCreateInfoEXT},
     Entry, Instance,
 };
+use nalgebra::{Matrix4, Point3, Vector3};
 use std::ptr::{self};
 use std::{
     ffi::{c_void, CStr, CString},
     os::raw::c_char,
+    time::Instant,
 };
 use winit::{
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::EventLoop,
     window::{Window, WindowBuilder},
 };
 
 use vulky::{
+    allocator::MemoryAllocator,
+    bindless::{BindlessDescriptors, TextureHandle},
+    blend::BlendPreset,
     buffer::{
-        create_command_buffers, create_command_pool, create_frame_buffer, create_index_buffer, create_sync_objects,
-        create_vertex_buffer, record_command_buffer, MAX_FRAMES_IN_FLIGHT,
+        create_command_buffers, create_command_pool, create_frame_buffer, create_sync_objects, record_command_buffer,
+        MAX_FRAMES_IN_FLIGHT,
     },
-    constant::{validation, version},
-    device::{create_logical_device, pick_physical_device},
-    pipeline::{create_pipeline_layout, create_render_pass},
-    platform, utility, SwapChainSupportDetails,
+    constant::{render, validation, version, INDICES, OVERLAY_VERTICES, VERTICES},
+    deletion::DeletionQueue,
+    depth::{find_depth_format, DepthBuffer, DepthConfig},
+    descriptor::{DescriptorAllocator, DescriptorBinding, DescriptorLayoutCache, DescriptorWriter},
+    device::{create_logical_device, pick_physical_device, DeviceFeatures},
+    mesh::Mesh,
+    mipmap::MipGeneration,
+    msaa::{max_usable_sample_count, ColorTarget, MsaaConfig},
+    pipeline::{PipelineDescription, PipelineTarget, SpecializationValue},
+    platform,
+    render_mode::{RenderMode, RenderModePipelines},
+    render_pass::{create_render_pass, AttachmentInfo, FramebufferAttachment, RenderPassDescription},
+    render_queue::{DrawItem, RenderQueue},
+    rendering::{DynamicAttachment, FrameTarget, RenderPath},
+    sampler::{supports_linear_filter, SamplerCache, SamplerDescription},
+    texture::Texture,
+    uniform::{look_at, perspective, Transforms, UniformBuffers},
+    upload::{UploadContext, UploadTicket, DEFAULT_STAGING_SIZE},
+    utility, FamilyQueue, SwapChainSupportDetails,
 };
 
-mod texture;
 mod types;
 
 /// The Vulkan SDK version that started requiring the portability subset extension for macOS.
@@ -97,6 +116,34 @@ fn main() {
                             app.minimized = false;
                         }
                     }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::M),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let samples = app.msaa.next_sample_count(app.max_samples);
+                        if let Err(e) = app.set_msaa_samples(samples) {
+                            eprintln!("msaa {:?} failed, keeping {:?}: {}", samples, app.msaa.samples, e);
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::R),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let mode = app.render_mode.next(&app.features);
+                        if let Err(e) = app.set_render_mode(mode) {
+                            eprintln!("render mode {:?} failed, keeping {:?}: {}", mode, app.render_mode, e);
+                        }
+                    }
 
                     _ => {}
                 },
@@ -106,6 +153,14 @@ fn main() {
     }
 }
 
+/// Which of the render mode's pipelines draws a mesh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Layer {
+    Opaque,
+    /// alpha blended on top, sorted back to front
+    Overlay,
+}
+
 struct VulkanApp {
     /// Global state for the app
     /// includes application specific info, including layers and extensions
@@ -120,6 +175,8 @@ struct VulkanApp {
     /// it is the interface to communicate with the gpu,
     /// has all info about the capabilities of the gpu.
     physical_device: vk::PhysicalDevice,
+    /// optional features that ended up enabled on the device
+    features: DeviceFeatures,
     /// Serves as a handle to interact with Vulkan API
     /// like managing vulkan resources, like (command buffers, queue handles, swapchain, pipeline, etc)
     /// Also used to enable extensions
@@ -143,14 +200,57 @@ struct VulkanApp {
     swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
 
+    /// sub-allocates the render targets
+    allocator: MemoryAllocator,
+
+    // Depth and msaa color, resized with the swapchain and rebuilt when the sample count changes
+    depth_config: DepthConfig,
+    depth_format: vk::Format,
+    depth_buffer: DepthBuffer,
+    msaa: MsaaConfig,
+    max_samples: vk::SampleCountFlags,
+    color_target: ColorTarget,
+
     // Pipeline
+    /// render pass and framebuffers are only created for the render pass path
+    render_path: RenderPath,
+    render_pass_description: RenderPassDescription,
     render_pass: vk::RenderPass,
-    pipeline_layout: vk::PipelineLayout,
+    /// None until create_pipeline
+    render_modes: Option<RenderModePipelines>,
+    render_mode: RenderMode,
+    /// pipeline of the current render mode and the blending it was built with
     pipeline: vk::Pipeline,
+    pipeline_blend: BlendPreset,
+    /// the same mode blended with OVERLAY_OPACITY, for the overlay layer
+    overlay_pipeline: vk::Pipeline,
+    overlay_blend: BlendPreset,
+    /// refilled every frame
+    render_queue: RenderQueue,
+    /// global texture and buffer arrays, None without descriptor indexing
+    bindless: Option<BindlessDescriptors>,
+
+    // descriptors
+    descriptor_layouts: DescriptorLayoutCache,
+    descriptor_allocator: DescriptorAllocator,
+    /// set 0 of every graphics pipeline, one per frame in flight
+    uniforms: UniformBuffers<Transforms>,
+    /// every sampler in use, destroyed with the device
+    samplers: SamplerCache,
+    /// sampled through set 1, None once destroyed
+    texture: Option<Texture>,
+    /// set 1 of every graphics pipeline, the bindless set when there is one and a set with just
+    /// `texture` otherwise
+    texture_set_layout: vk::DescriptorSetLayout,
+    texture_set: vk::DescriptorSet,
+    /// index of `texture` in the bindless array, pushed with every draw
+    texture_handle: Option<TextureHandle>,
+    start_time: Instant,
 
     //CommandPool
     graphic_command_pool: vk::CommandPool,
-    transfer_command_pool: vk::CommandPool,
+    /// staging ring and command pool on the transfer queue
+    uploads: UploadContext,
 
     // buffers
     swapchain_framebuffers: Vec<vk::Framebuffer>,
@@ -165,11 +265,12 @@ struct VulkanApp {
     framebuffer_resized: bool,
     minimized: bool,
 
-    vertex_buffer: vk::Buffer,
-    vertex_memory: vk::DeviceMemory,
-
-    index_buffer: vk::Buffer,
-    index_memory: vk::DeviceMemory,
+    /// resources dropped while frames may still use them
+    deletion_queue: DeletionQueue,
+    /// every mesh is drawn each frame, cleared in destroy before the allocator goes
+    meshes: Vec<(Layer, Mesh)>,
+    /// meshes still uploading, moved to `meshes` once their ticket completed
+    pending_meshes: Vec<(UploadTicket, Layer, Mesh)>,
 }
 impl VulkanApp {
     unsafe fn new(window: &Window) -> Result<Self> {
@@ -180,7 +281,18 @@ impl VulkanApp {
         let (debug_util_loader, debug_messenger) = setup_debug_utils(&entry, &instance)?;
 
         let physical_device = pick_physical_device(&instance, &surface_loader, &surface)?;
-        let (device, queue_family) = create_logical_device(physical_device, &instance, surface, &surface_loader)?;
+        let requested_features = DeviceFeatures {
+            dynamic_rendering: render::PREFER_DYNAMIC_RENDERING,
+            sample_rate_shading: render::SAMPLE_SHADING.is_some(),
+            sampler_anisotropy: true,
+            fill_mode_non_solid: true,
+            descriptor_indexing: render::BINDLESS,
+            memory_budget: true,
+            timeline_semaphore: true,
+            buffer_device_address: false,
+        };
+        let (device, queue_family, features) =
+            create_logical_device(physical_device, &instance, surface, &surface_loader, &requested_features)?;
         let graphics_queue = device.get_device_queue(queue_family.graphics_family.unwrap(), 0);
         let present_queue = device.get_device_queue(queue_family.present_family.unwrap(), 0);
         let transfer_queue = device.get_device_queue(queue_family.transfer_family.unwrap(), 0);
@@ -188,28 +300,136 @@ impl VulkanApp {
         let (swapchain_loader, swapchain, swapchain_extent, swapchain_format, swapchain_images, swapchain_image_views) =
             SwapChainSupportDetails::create_swapchain(&instance, &device, &surface_loader, surface, physical_device)?;
 
-        let render_pass = create_render_pass(swapchain_format, &device)?;
-        let swapchain_framebuffers = create_frame_buffer(&device, &swapchain_image_views, render_pass, swapchain_extent)?;
-        let (pipeline, pipeline_layout) = create_pipeline_layout(&device, swapchain_extent, render_pass)?;
+        let deletion_queue = DeletionQueue::new();
+        let mut bindless = if features.descriptor_indexing {
+            Some(BindlessDescriptors::new(
+                &device,
+                &instance,
+                physical_device,
+                &features,
+                &deletion_queue,
+                render::BINDLESS_MAX_TEXTURES,
+                render::BINDLESS_MAX_BUFFERS,
+            )?)
+        } else {
+            None
+        };
+        println!("bindless descriptors: {}", bindless.is_some());
+
+        let render_path = RenderPath::choose(render::PREFER_DYNAMIC_RENDERING, &features);
+        println!("render path: {:?}", render_path);
+
+        let depth_config = DepthConfig {
+            reverse_z: render::REVERSE_Z,
+            ..Default::default()
+        };
+        let depth_format = find_depth_format(&instance, physical_device, depth_config.stencil)?;
+
+        let max_samples = max_usable_sample_count(&instance, physical_device);
+        let msaa = MsaaConfig {
+            samples: render::MSAA_SAMPLES,
+            sample_shading: render::SAMPLE_SHADING,
+        }
+        .clamped(max_samples, &features);
+        println!("msaa: {:?} (max {:?})", msaa.samples, max_samples);
+
+        let mut allocator = MemoryAllocator::new(&instance, physical_device, &features);
 
         let graphic_command_pool = create_command_pool(&device, &queue_family.graphics_family)?;
-        let transfer_command_pool = create_command_pool(&device, &queue_family.transfer_family)?;
-        let (vertex_buffer, vertex_memory) =
-            create_vertex_buffer(&device, physical_device, &instance, transfer_command_pool, transfer_queue)?;
-        let (index_buffer, index_memory) =
-            create_index_buffer(&device, &instance, physical_device, transfer_command_pool, transfer_queue)?;
+        let mut uploads = UploadContext::new(
+            &device,
+            &instance,
+            physical_device,
+            FamilyQueue {
+                family: queue_family.transfer_family.unwrap(),
+                queue: transfer_queue,
+            },
+            FamilyQueue {
+                family: queue_family.graphics_family.unwrap(),
+                queue: graphics_queue,
+            },
+            &features,
+            DEFAULT_STAGING_SIZE,
+        )?;
+        let quad = Mesh::with_u16_indices(&device, &mut allocator, &deletion_queue, &mut uploads, &VERTICES, &INDICES)?;
+        let overlay = Mesh::with_u16_indices(
+            &device,
+            &mut allocator,
+            &deletion_queue,
+            &mut uploads,
+            &OVERLAY_VERTICES,
+            &INDICES,
+        )?;
+        let mipmaps = MipGeneration::for_format(&instance, physical_device, vk::Format::R8G8B8A8_SRGB);
+        let texture = Texture::from_file(
+            &device,
+            &mut allocator,
+            &deletion_queue,
+            &mut uploads,
+            render::TEXTURE_PATH,
+            mipmaps,
+        )?;
+        // streams in while the first frames render, the quad is only drawn once both are done
+        let quad_ticket = uploads.flush(&device)?;
+
+        let mut descriptor_layouts = DescriptorLayoutCache::new();
+        let mut descriptor_allocator = DescriptorAllocator::new(16);
+        let uniforms = UniformBuffers::new(
+            &device,
+            &mut allocator,
+            &mut descriptor_layouts,
+            &mut descriptor_allocator,
+            MAX_FRAMES_IN_FLIGHT as usize,
+            vk::ShaderStageFlags::VERTEX,
+        )?;
+        let mut samplers = SamplerCache::new(&instance, physical_device, &features);
+        let linear_filter = supports_linear_filter(&instance, physical_device, texture.format());
+        let sampler = samplers.get(
+            &device,
+            &SamplerDescription::linear()
+                .with_anisotropy(16.0)
+                .with_linear_filter_support(linear_filter),
+        )?;
+        let (texture_set_layout, texture_set, texture_handle) = match bindless.as_mut() {
+            Some(bindless) => {
+                let handle = bindless.register_texture(&device, texture.view(), sampler, texture.layout())?;
+                (bindless.set_layout, bindless.set, Some(handle))
+            }
+            None => {
+                let layout = descriptor_layouts.get(
+                    &device,
+                    &[DescriptorBinding::new(
+                        0,
+                        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        vk::ShaderStageFlags::FRAGMENT,
+                    )],
+                )?;
+                let set = descriptor_allocator.allocate(&device, &descriptor_layouts, layout)?;
+                DescriptorWriter::new()
+                    .write_image(
+                        0,
+                        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        texture.view(),
+                        texture.layout(),
+                        sampler,
+                    )
+                    .update(&device, set);
+                (layout, set, None)
+            }
+        };
 
         let command_buffers = create_command_buffers(&device, graphic_command_pool)?;
         let (in_flights, image_availables, render_finisheds) = create_sync_objects(&device)?;
-        Ok(Self {
+        let mut app = Self {
             instance,
             entry,
             physical_device,
+            features,
             device,
             graphics_queue,
             present_queue,
             transfer_queue,
-            transfer_command_pool,
+            uploads,
             surface,
             surface_loader,
             swapchain,
@@ -218,10 +438,34 @@ impl VulkanApp {
             swapchain_format,
             swapchain_images,
             swapchain_image_views,
-            swapchain_framebuffers,
-            render_pass,
-            pipeline_layout,
-            pipeline,
+            swapchain_framebuffers: vec![],
+            allocator,
+            depth_config,
+            depth_format,
+            depth_buffer: DepthBuffer::default(),
+            msaa,
+            max_samples,
+            color_target: ColorTarget::default(),
+            render_path,
+            render_pass_description: RenderPassDescription::new(),
+            render_pass: vk::RenderPass::null(),
+            render_modes: None,
+            render_mode: RenderMode::Shaded,
+            pipeline: vk::Pipeline::null(),
+            pipeline_blend: BlendPreset::Opaque,
+            overlay_pipeline: vk::Pipeline::null(),
+            overlay_blend: BlendPreset::Alpha,
+            render_queue: RenderQueue::new(),
+            bindless,
+            descriptor_layouts,
+            descriptor_allocator,
+            uniforms,
+            samplers,
+            texture: Some(texture),
+            texture_set_layout,
+            texture_set,
+            texture_handle,
+            start_time: Instant::now(),
             graphic_command_pool,
             command_buffers,
             debug_util_loader,
@@ -232,11 +476,13 @@ impl VulkanApp {
             current_frame: 0,
             framebuffer_resized: false,
             minimized: false,
-            vertex_buffer,
-            vertex_memory,
-            index_buffer,
-            index_memory,
-        })
+            deletion_queue,
+            meshes: vec![],
+            pending_meshes: vec![(quad_ticket, Layer::Opaque, quad), (quad_ticket, Layer::Overlay, overlay)],
+        };
+        app.create_render_targets()?;
+        app.create_pipeline()?;
+        Ok(app)
     }
 
     pub unsafe fn draw_frame(&mut self) -> VkResult<()> {
@@ -268,18 +514,62 @@ impl VulkanApp {
         };
 
         self.device.reset_fences(&wait_fences)?;
+        self.deletion_queue
+            .next_frame(&self.device, &mut self.allocator, MAX_FRAMES_IN_FLIGHT as u64);
+        self.collect_uploaded_meshes()?;
         self.device
             .reset_command_buffer(self.command_buffers[self.current_frame], vk::CommandBufferResetFlags::empty())?;
+        self.update_uniforms();
+        let clear_values = self.render_pass_description.clear_values();
+        let target = match self.render_path {
+            RenderPath::RenderPass => FrameTarget::RenderPass {
+                render_pass: self.render_pass,
+                framebuffer: self.swapchain_framebuffers[image_index as usize],
+                clear_values: &clear_values,
+            },
+            RenderPath::Dynamic => FrameTarget::Dynamic {
+                color: if self.msaa.is_enabled() {
+                    DynamicAttachment::color(self.color_target.image, self.color_target.view).with_resolve(
+                        self.swapchain_images[image_index as usize],
+                        self.swapchain_image_views[image_index as usize],
+                    )
+                } else {
+                    DynamicAttachment::color(
+                        self.swapchain_images[image_index as usize],
+                        self.swapchain_image_views[image_index as usize],
+                    )
+                },
+                depth: Some(DynamicAttachment::depth(
+                    self.depth_buffer.image,
+                    self.depth_buffer.view,
+                    self.depth_buffer.aspect_mask(),
+                    self.depth_config.clear_value(),
+                )),
+            },
+        };
+        self.render_queue.clear();
+        for (layer, mesh) in self.meshes.iter() {
+            let (pipeline, blend) = match layer {
+                Layer::Opaque => (self.pipeline, self.pipeline_blend),
+                Layer::Overlay => (self.overlay_pipeline, self.overlay_blend),
+            };
+            let item = DrawItem {
+                texture: self.texture_handle,
+                ..mesh.draw_item(pipeline, 0.0)
+            };
+            self.render_queue.push(item, blend);
+        }
+        self.render_queue.sort();
         record_command_buffer(
             &self.device,
             self.command_buffers[self.current_frame],
-            self.render_pass,
-            &self.swapchain_framebuffers,
-            image_index,
+            &target,
             self.swapchain_extent,
-            self.pipeline,
-            self.vertex_buffer,
-            self.index_buffer,
+            self.render_modes
+                .as_ref()
+                .map_or(vk::PipelineLayout::null(), |modes| modes.layout()),
+            &[self.uniforms.sets[self.current_frame], self.texture_set],
+            &self.render_queue,
         )?;
 
         let wait_semaphores = [self.image_availables[self.current_frame]];
@@ -344,24 +634,63 @@ impl VulkanApp {
             self.device.destroy_semaphore(self.render_finisheds[i], None);
         }
         self.device.destroy_command_pool(self.graphic_command_pool, None);
-        self.device.destroy_command_pool(self.transfer_command_pool, None);
+        self.uploads.destroy(&self.device);
 
         self.clean_swapchain();
 
-        self.device.destroy_buffer(self.vertex_buffer, None);
-        self.device.destroy_buffer(self.index_buffer, None);
-        self.device.free_memory(self.vertex_memory, None);
-        self.device.free_memory(self.index_memory, None);
+        self.meshes.clear();
+        self.pending_meshes.clear();
+        if let (Some(bindless), Some(handle)) = (self.bindless.as_mut(), self.texture_handle.take()) {
+            bindless.release_texture(handle);
+        }
+        self.texture = None;
+        self.deletion_queue.flush(&self.device, &mut self.allocator);
 
-        self.device.destroy_pipeline(self.pipeline, None);
-        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
-        self.device.destroy_render_pass(self.render_pass, None);
+        self.destroy_pipeline();
+        if let Some(bindless) = self.bindless.as_mut() {
+            bindless.destroy(&self.device);
+        }
+        self.uniforms.destroy(&self.device, &mut self.allocator);
+        self.samplers.destroy(&self.device);
+        self.descriptor_allocator.destroy(&self.device);
+        self.descriptor_layouts.destroy(&self.device);
+        self.allocator.destroy(&self.device);
 
         self.surface_loader.destroy_surface(self.surface, None);
         self.device.destroy_device(None);
         self.instance.destroy_instance(None);
     }
 
+    /// Starts drawing the meshes whose upload finished, without waiting on the others, and switches
+    /// drawn meshes over to replaced geometry that finished uploading.
+    unsafe fn collect_uploaded_meshes(&mut self) -> VkResult<()> {
+        for (_, mesh) in self.meshes.iter_mut() {
+            mesh.update(&self.device, &mut self.uploads)?;
+        }
+        let mut index = 0;
+        while index < self.pending_meshes.len() {
+            if self.uploads.is_complete(&self.device, self.pending_meshes[index].0)? {
+                let (_, layer, mesh) = self.pending_meshes.swap_remove(index);
+                self.meshes.push((layer, mesh));
+            } else {
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Spins the quad, the frame's fence has been waited on so its uniform buffer is not in use.
+    unsafe fn update_uniforms(&mut self) {
+        let time = self.start_time.elapsed().as_secs_f32();
+        let aspect = self.swapchain_extent.width as f32 / self.swapchain_extent.height.max(1) as f32;
+        let transforms = Transforms {
+            model: Matrix4::from_axis_angle(&Vector3::z_axis(), time * std::f32::consts::FRAC_PI_2),
+            view: look_at(Point3::new(2.0, 2.0, 2.0), Point3::origin(), Vector3::z()),
+            projection: perspective(45f32.to_radians(), aspect, 0.1, 10.0, self.depth_config.reverse_z),
+        };
+        self.uniforms.update(self.current_frame, &transforms);
+    }
+
     pub unsafe fn recreate_swapchain(&mut self) -> VkResult<()> {
         self.device.device_wait_idle()?;
         self.clean_swapchain();
@@ -381,21 +710,13 @@ impl VulkanApp {
             self.physical_device,
         )?;
 
-        self.swapchain_framebuffers = create_frame_buffer(
-            &self.device,
-            &self.swapchain_image_views,
-            self.render_pass,
-            self.swapchain_extent,
-        )?;
+        self.create_attachments()?;
 
         Ok(())
     }
 
     unsafe fn clean_swapchain(&mut self) {
-        while self.swapchain_framebuffers.len() > 0 {
-            let e = self.swapchain_framebuffers.pop().unwrap();
-            self.device.destroy_framebuffer(e, None);
-        }
+        self.destroy_attachments();
 
         for _ in 0..self.swapchain_image_views.len() {
             let image_view = self.swapchain_image_views.pop().unwrap();
@@ -404,6 +725,200 @@ impl VulkanApp {
 
         self.swapchain_loader.destroy_swapchain(self.swapchain, None);
     }
+
+    /// Render pass for the current sample count, then the attachments drawn into.
+    unsafe fn create_render_targets(&mut self) -> Result<()> {
+        self.create_render_pass()?;
+        self.create_attachments()?;
+        Ok(())
+    }
+
+    unsafe fn create_render_pass(&mut self) -> Result<()> {
+        self.render_pass_description = RenderPassDescription::forward(
+            self.swapchain_format,
+            AttachmentInfo::depth(self.depth_format).with_clear(self.depth_config.clear_value()),
+            self.msaa.samples,
+        );
+        if self.render_path == RenderPath::RenderPass {
+            self.render_pass = create_render_pass(&self.device, &self.render_pass_description)?;
+        }
+        Ok(())
+    }
+
+    /// Depth buffer, msaa color target and framebuffers, sized to the swapchain.
+    unsafe fn create_attachments(&mut self) -> VkResult<()> {
+        self.depth_buffer = DepthBuffer::new(
+            &self.device,
+            &mut self.allocator,
+            self.swapchain_extent,
+            self.depth_format,
+            self.msaa.samples,
+        )?;
+        if self.msaa.is_enabled() {
+            self.color_target = ColorTarget::new(
+                &self.device,
+                &mut self.allocator,
+                self.swapchain_extent,
+                self.swapchain_format,
+                self.msaa.samples,
+            )?;
+        }
+
+        if self.render_path == RenderPath::RenderPass {
+            // same order as RenderPassDescription::forward
+            let attachments = if self.msaa.is_enabled() {
+                vec![
+                    FramebufferAttachment::View(self.color_target.view),
+                    FramebufferAttachment::View(self.depth_buffer.view),
+                    FramebufferAttachment::Swapchain,
+                ]
+            } else {
+                vec![
+                    FramebufferAttachment::Swapchain,
+                    FramebufferAttachment::View(self.depth_buffer.view),
+                ]
+            };
+            self.swapchain_framebuffers = create_frame_buffer(
+                &self.device,
+                &self.swapchain_image_views,
+                &attachments,
+                self.render_pass,
+                self.swapchain_extent,
+            )?;
+        }
+        Ok(())
+    }
+
+    unsafe fn destroy_attachments(&mut self) {
+        while let Some(framebuffer) = self.swapchain_framebuffers.pop() {
+            self.device.destroy_framebuffer(framebuffer, None);
+        }
+        self.depth_buffer.destroy(&self.device, &mut self.allocator);
+        // only created with msaa on
+        if self.color_target.image != vk::Image::null() {
+            self.color_target.destroy(&self.device, &mut self.allocator);
+        }
+        self.color_target = ColorTarget::default();
+    }
+
+    unsafe fn create_pipeline(&mut self) -> Result<()> {
+        let target = match self.render_path {
+            RenderPath::RenderPass => PipelineTarget::render_pass(self.render_pass, &self.render_pass_description, 0),
+            RenderPath::Dynamic => PipelineTarget::dynamic(self.swapchain_format).with_depth_format(self.depth_format),
+        };
+        let mut description = PipelineDescription {
+            depth: Some(self.depth_config),
+            msaa: self.msaa,
+            set_layouts: vec![self.uniforms.set_layout, self.texture_set_layout],
+            ..Default::default()
+        };
+        if self.bindless.is_some() {
+            // indexes the texture array with the pushed handle
+            description.fragment_shader = "shaders/spv/frag_bindless.spv".to_owned();
+            let limits = self.instance.get_physical_device_properties(self.physical_device).limits;
+            description = description.with_push_constants::<TextureHandle>(&limits)?;
+        }
+        self.render_modes = Some(RenderModePipelines::new(
+            &self.device,
+            description,
+            self.swapchain_extent,
+            target,
+        )?);
+        self.set_render_mode(self.render_mode)
+    }
+
+    /// Switches to the pipeline of `mode`, creating it on first use. The current pipelines stay on failure.
+    pub unsafe fn set_render_mode(&mut self, mode: RenderMode) -> Result<()> {
+        let supported = mode.or_supported(&self.features);
+        if supported != mode {
+            println!(
+                "render mode {:?} needs fillModeNonSolid, falling back to {:?}",
+                mode, supported
+            );
+        }
+        let mode = supported;
+        let render_modes = self.render_modes.as_mut().expect("pipelines are not created");
+        let pipeline = render_modes.get(&self.device, mode)?;
+        let description = mode.description(render_modes.base());
+        let pipeline_blend = description.blend;
+
+        // modes that already blend keep their preset
+        let mut overlay = if description.blend.is_transparent() {
+            description
+        } else {
+            description.with_blend(BlendPreset::Alpha)
+        };
+        overlay.constants.set(
+            render::OPACITY_CONSTANT_ID,
+            SpecializationValue::Float(render::OVERLAY_OPACITY),
+        );
+        self.overlay_pipeline = render_modes.variant(&self.device, &overlay)?;
+        self.overlay_blend = overlay.blend;
+        self.pipeline = pipeline;
+        self.pipeline_blend = pipeline_blend;
+        if mode != self.render_mode {
+            println!("render mode: {:?}", mode);
+        }
+        self.render_mode = mode;
+        Ok(())
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if let Some(mut render_modes) = self.render_modes.take() {
+            render_modes.destroy(&self.device);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.overlay_pipeline = vk::Pipeline::null();
+        self.device.destroy_render_pass(self.render_pass, None);
+        self.render_pass = vk::RenderPass::null();
+    }
+
+    /// Switches the msaa sample count, rebuilding the render pass, attachments and pipeline. The pipelines
+    /// are built first, when that fails the current render pass and pipelines stay.
+    pub unsafe fn set_msaa_samples(&mut self, samples: vk::SampleCountFlags) -> Result<()> {
+        let msaa = MsaaConfig {
+            samples,
+            sample_shading: render::SAMPLE_SHADING,
+        }
+        .clamped(self.max_samples, &self.features);
+        if msaa == self.msaa {
+            return Ok(());
+        }
+
+        self.device.device_wait_idle()?;
+        let previous = (
+            self.msaa,
+            self.render_pass_description.clone(),
+            self.render_pass,
+            self.render_modes.take(),
+            [self.pipeline, self.overlay_pipeline],
+            [self.pipeline_blend, self.overlay_blend],
+        );
+        self.msaa = msaa;
+        self.render_pass = vk::RenderPass::null();
+        if let Err(e) = self.create_render_pass().and_then(|_| self.create_pipeline()) {
+            self.destroy_pipeline();
+            (
+                self.msaa,
+                self.render_pass_description,
+                self.render_pass,
+                self.render_modes,
+                [self.pipeline, self.overlay_pipeline],
+                [self.pipeline_blend, self.overlay_blend],
+            ) = previous;
+            return Err(e);
+        }
+
+        let (_, _, render_pass, render_modes, _, _) = previous;
+        if let Some(mut render_modes) = render_modes {
+            render_modes.destroy(&self.device);
+        }
+        self.device.destroy_render_pass(render_pass, None);
+        self.destroy_attachments();
+        self.create_attachments()?;
+        println!("msaa: {:?}", self.msaa.samples);
+        Ok(())
+    }
 }
 
 unsafe fn create_instance(entry: &ash::Entry) -> Result<ash::Instance> {
@@ -0,0 +1,249 @@
+use std::ffi::c_void;
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, MemoryPropertyFlags},
+};
+
+/// Share of a heap handed out when `VK_EXT_memory_budget` is missing, the rest is left to other processes.
+const FALLBACK_BUDGET_PERCENT: vk::DeviceSize = 80;
+
+/// Memory properties a resource must have, and the ones it would like to have.
+///
+/// e.g. upload buffers require HOST_VISIBLE | HOST_COHERENT and prefer DEVICE_LOCAL,
+/// which lands them in device memory on unified memory gpus and in system memory elsewhere.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryProperties {
+    pub required: MemoryPropertyFlags,
+    pub preferred: MemoryPropertyFlags,
+}
+
+impl MemoryProperties {
+    pub fn new(required: MemoryPropertyFlags) -> Self {
+        Self {
+            required,
+            preferred: MemoryPropertyFlags::empty(),
+        }
+    }
+
+    pub fn prefer(mut self, preferred: MemoryPropertyFlags) -> Self {
+        self.preferred = preferred;
+        self
+    }
+
+    /// Host visible and coherent, device local if the device has such memory.
+    pub fn upload() -> Self {
+        Self::new(MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT)
+            .prefer(MemoryPropertyFlags::DEVICE_LOCAL)
+    }
+
+    /// Host visible and cached for reading back on the cpu.
+    pub fn readback() -> Self {
+        Self::new(MemoryPropertyFlags::HOST_VISIBLE).prefer(MemoryPropertyFlags::HOST_CACHED)
+    }
+}
+
+impl From<MemoryPropertyFlags> for MemoryProperties {
+    fn from(required: MemoryPropertyFlags) -> Self {
+        Self::new(required)
+    }
+}
+
+/// How much of a heap this process may use, and how much it is using.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapBudget {
+    pub budget: vk::DeviceSize,
+    pub usage: vk::DeviceSize,
+}
+
+impl HeapBudget {
+    pub fn available(&self) -> vk::DeviceSize {
+        self.budget.saturating_sub(self.usage)
+    }
+}
+
+/// Budget of every heap. With `memory_budget` enabled the driver reports it, including what other
+/// processes use. Otherwise a fixed share of the heap size is assumed and `own_usage` is the usage.
+pub unsafe fn heap_budgets(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    memory_budget: bool,
+    own_usage: &[vk::DeviceSize],
+) -> Vec<HeapBudget> {
+    let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceMemoryProperties2::default();
+    if memory_budget {
+        properties.p_next = &mut budget_properties as *mut vk::PhysicalDeviceMemoryBudgetPropertiesEXT as *mut c_void;
+    }
+    instance.get_physical_device_memory_properties2(physical_device, &mut properties);
+
+    let heaps = &properties.memory_properties.memory_heaps[..properties.memory_properties.memory_heap_count as usize];
+    heaps
+        .iter()
+        .enumerate()
+        .map(|(index, heap)| {
+            if memory_budget {
+                HeapBudget {
+                    budget: budget_properties.heap_budget[index],
+                    usage: budget_properties.heap_usage[index],
+                }
+            } else {
+                HeapBudget {
+                    budget: heap.size / 100 * FALLBACK_BUDGET_PERCENT,
+                    usage: own_usage.get(index).copied().unwrap_or(0),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Memory types allowed by `type_bits` that have every required property, best first.
+///
+/// Types with more of the preferred properties come first, ties keep the driver order which already
+/// lists the faster types first. Types whose heap has less than `size` left in `budgets` are left out.
+pub fn memory_type_candidates(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    properties: MemoryProperties,
+    size: vk::DeviceSize,
+    budgets: Option<&[HeapBudget]>,
+) -> Vec<u32> {
+    let mut candidates: Vec<u32> = (0..memory_properties.memory_type_count)
+        .filter(|index| {
+            let memory_type = memory_properties.memory_types[*index as usize];
+            let fits_budget = budgets
+                .and_then(|budgets| budgets.get(memory_type.heap_index as usize))
+                .is_none_or(|budget| budget.available() >= size);
+            type_bits & (1 << index) != 0 && memory_type.property_flags.contains(properties.required) && fits_budget
+        })
+        .collect();
+    // stable, so equal scores stay in driver order
+    candidates.sort_by_key(|index| {
+        let flags = memory_properties.memory_types[*index as usize].property_flags;
+        std::cmp::Reverse((flags & properties.preferred).as_raw().count_ones())
+    });
+    candidates
+}
+
+/// Best memory type for the request, ERROR_OUT_OF_DEVICE_MEMORY when none has the required properties
+/// or every matching heap is over budget.
+pub fn select_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    properties: MemoryProperties,
+    size: vk::DeviceSize,
+    budgets: Option<&[HeapBudget]>,
+) -> VkResult<u32> {
+    memory_type_candidates(memory_properties, type_bits, properties, size, budgets)
+        .first()
+        .copied()
+        .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_LOCAL: MemoryPropertyFlags = MemoryPropertyFlags::DEVICE_LOCAL;
+    const HOST_VISIBLE: MemoryPropertyFlags = MemoryPropertyFlags::HOST_VISIBLE;
+    const HOST_COHERENT: MemoryPropertyFlags = MemoryPropertyFlags::HOST_COHERENT;
+    const HOST_CACHED: MemoryPropertyFlags = MemoryPropertyFlags::HOST_CACHED;
+
+    /// memory types given as (heap, flags), every heap 1 GiB
+    fn properties(types: &[(u32, MemoryPropertyFlags)]) -> vk::PhysicalDeviceMemoryProperties {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: types.len() as u32,
+            ..Default::default()
+        };
+        for (index, (heap_index, property_flags)) in types.iter().enumerate() {
+            properties.memory_types[index] = vk::MemoryType {
+                property_flags: *property_flags,
+                heap_index: *heap_index,
+            };
+            properties.memory_heap_count = properties.memory_heap_count.max(heap_index + 1);
+        }
+        for heap in properties.memory_heaps[..properties.memory_heap_count as usize].iter_mut() {
+            heap.size = 1 << 30;
+        }
+        properties
+    }
+
+    /// a discrete gpu: vram, system memory, and a small host visible window into vram
+    fn discrete() -> vk::PhysicalDeviceMemoryProperties {
+        properties(&[
+            (0, DEVICE_LOCAL),
+            (1, HOST_VISIBLE | HOST_COHERENT),
+            (1, HOST_VISIBLE | HOST_COHERENT | HOST_CACHED),
+            (0, DEVICE_LOCAL | HOST_VISIBLE | HOST_COHERENT),
+        ])
+    }
+
+    #[test]
+    fn required_flags_filter() {
+        let properties = discrete();
+        let candidates = |required| memory_type_candidates(&properties, !0, MemoryProperties::new(required), 1, None);
+        assert_eq!(candidates(DEVICE_LOCAL), [0, 3]);
+        assert_eq!(candidates(HOST_VISIBLE | HOST_COHERENT), [1, 2, 3]);
+        assert_eq!(candidates(HOST_VISIBLE | HOST_CACHED), [2]);
+        assert_eq!(candidates(MemoryPropertyFlags::LAZILY_ALLOCATED), []);
+        // no requirements, every type in driver order
+        assert_eq!(candidates(MemoryPropertyFlags::empty()), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn type_bits_filter() {
+        let properties = discrete();
+        let candidates = memory_type_candidates(&properties, 0b0110, MemoryProperties::upload(), 1, None);
+        assert_eq!(candidates, [1, 2]);
+        assert_eq!(
+            select_memory_type(&properties, 0b0001, MemoryProperties::upload(), 1, None),
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+        );
+    }
+
+    #[test]
+    fn preferred_flags_sort_first() {
+        let properties = discrete();
+        // device local upload memory first, the rest stays in driver order
+        let candidates = memory_type_candidates(&properties, !0, MemoryProperties::upload(), 1, None);
+        assert_eq!(candidates, [3, 1, 2]);
+
+        let candidates = memory_type_candidates(&properties, !0, MemoryProperties::readback(), 1, None);
+        assert_eq!(candidates, [2, 1, 3]);
+
+        // more preferred flags win
+        let properties = MemoryProperties::new(HOST_VISIBLE).prefer(HOST_COHERENT | HOST_CACHED | DEVICE_LOCAL);
+        assert_eq!(memory_type_candidates(&discrete(), !0, properties, 1, None), [2, 3, 1]);
+    }
+
+    #[test]
+    fn heaps_over_budget_skipped() {
+        let properties = discrete();
+        let budgets = [
+            HeapBudget {
+                budget: 1000,
+                usage: 900,
+            },
+            HeapBudget { budget: 1000, usage: 0 },
+        ];
+        // heap 0 has 100 bytes left
+        let candidates = memory_type_candidates(&properties, !0, MemoryProperties::upload(), 100, Some(&budgets));
+        assert_eq!(candidates, [3, 1, 2]);
+        let candidates = memory_type_candidates(&properties, !0, MemoryProperties::upload(), 101, Some(&budgets));
+        assert_eq!(candidates, [1, 2]);
+        assert_eq!(
+            select_memory_type(&properties, !0, DEVICE_LOCAL.into(), 101, Some(&budgets)),
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+        );
+
+        // a heap without a budget entry is not limited
+        let candidates = memory_type_candidates(&properties, !0, DEVICE_LOCAL.into(), 1 << 40, Some(&budgets[..0]));
+        assert_eq!(candidates, [0, 3]);
+    }
+
+    #[test]
+    fn budget_available_saturates() {
+        let over = HeapBudget { budget: 100, usage: 150 };
+        assert_eq!(over.available(), 0);
+    }
+}
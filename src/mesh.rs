@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    allocator::MemoryAllocator,
+    constant::Vertex,
+    deletion::DeletionQueue,
+    gpu_buffer::Buffer,
+    render_queue::DrawItem,
+    upload::{UploadContext, UploadTicket},
+};
+
+enum IndexBuffer {
+    U16(Buffer<u16>),
+    U32(Buffer<u32>),
+}
+
+impl IndexBuffer {
+    /// 16 bit indices when every index fits, they take half the memory and bandwidth. 0xFFFF is left to
+    /// u32 buffers as it restarts the primitive when primitive restart is on.
+    unsafe fn new(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        indices: &[u32],
+    ) -> VkResult<Self> {
+        let usage = vk::BufferUsageFlags::INDEX_BUFFER;
+        if indices.iter().all(|index| *index < u16::MAX as u32) {
+            let indices: Vec<u16> = indices.iter().map(|index| *index as u16).collect();
+            Ok(Self::U16(Buffer::with_data(
+                device, allocator, deletion, uploads, &indices, usage,
+            )?))
+        } else {
+            Ok(Self::U32(Buffer::with_data(
+                device, allocator, deletion, uploads, indices, usage,
+            )?))
+        }
+    }
+
+    fn handle(&self) -> vk::Buffer {
+        match self {
+            Self::U16(buffer) => buffer.handle(),
+            Self::U32(buffer) => buffer.handle(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::U16(buffer) => buffer.len(),
+            Self::U32(buffer) => buffer.len(),
+        }
+    }
+
+    fn index_type(&self) -> vk::IndexType {
+        match self {
+            Self::U16(_) => vk::IndexType::UINT16,
+            Self::U32(_) => vk::IndexType::UINT32,
+        }
+    }
+}
+
+/// New buffers still uploading, `index_buffer` is None when only the vertices change.
+struct Replacement<V: Copy> {
+    ticket: UploadTicket,
+    vertex_buffer: Buffer<V>,
+    index_buffer: Option<IndexBuffer>,
+}
+
+/// Indexed geometry in device local buffers, uploaded through an `UploadContext`.
+///
+/// Ready to draw once the upload ticket completed. Replacing the data creates new buffers that are
+/// drawn once `update` sees their upload completed, the old ones then go through the deletion queue so
+/// frames in flight can keep drawing them.
+pub struct Mesh<V: Copy = Vertex> {
+    vertex_buffer: Buffer<V>,
+    index_buffer: IndexBuffer,
+    /// oldest first, tickets complete in order
+    replacements: VecDeque<Replacement<V>>,
+}
+
+impl<V: Copy> Mesh<V> {
+    /// The index type follows the largest index, u16 if it fits and u32 otherwise.
+    pub unsafe fn new(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> VkResult<Self> {
+        let vertex_buffer = Buffer::with_data(
+            device,
+            allocator,
+            deletion,
+            uploads,
+            vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let index_buffer = IndexBuffer::new(device, allocator, deletion, uploads, indices)?;
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            replacements: VecDeque::new(),
+        })
+    }
+
+    /// Same as `new` for data that already has 16 bit indices.
+    pub unsafe fn with_u16_indices(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        vertices: &[V],
+        indices: &[u16],
+    ) -> VkResult<Self> {
+        let vertex_buffer = Buffer::with_data(
+            device,
+            allocator,
+            deletion,
+            uploads,
+            vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let index_buffer = IndexBuffer::U16(Buffer::with_data(
+            device,
+            allocator,
+            deletion,
+            uploads,
+            indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        )?);
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            replacements: VecDeque::new(),
+        })
+    }
+
+    /// Uploads new geometry and submits it. The current buffers are still drawn until `update` sees the
+    /// returned ticket completed.
+    pub unsafe fn replace(
+        &mut self,
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> VkResult<UploadTicket> {
+        let vertex_buffer = Buffer::with_data(
+            device,
+            allocator,
+            deletion,
+            uploads,
+            vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let index_buffer = IndexBuffer::new(device, allocator, deletion, uploads, indices)?;
+        let ticket = uploads.flush(device)?;
+        self.replacements.push_back(Replacement {
+            ticket,
+            vertex_buffer,
+            index_buffer: Some(index_buffer),
+        });
+        Ok(ticket)
+    }
+
+    /// New vertices with the same indices, e.g. for cpu animated geometry. Takes effect like `replace`.
+    pub unsafe fn replace_vertices(
+        &mut self,
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        vertices: &[V],
+    ) -> VkResult<UploadTicket> {
+        let vertex_buffer = Buffer::with_data(
+            device,
+            allocator,
+            deletion,
+            uploads,
+            vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let ticket = uploads.flush(device)?;
+        self.replacements.push_back(Replacement {
+            ticket,
+            vertex_buffer,
+            index_buffer: None,
+        });
+        Ok(ticket)
+    }
+
+    /// Switches to the newest replacement whose upload completed, call before recording draws.
+    /// Returns true if the buffers changed.
+    pub unsafe fn update(&mut self, device: &ash::Device, uploads: &mut UploadContext) -> VkResult<bool> {
+        let mut changed = false;
+        while let Some(replacement) = self.replacements.front() {
+            if !uploads.is_complete(device, replacement.ticket)? {
+                break;
+            }
+            let replacement = self.replacements.pop_front().unwrap();
+            // the old buffers drop into the deletion queue
+            self.vertex_buffer = replacement.vertex_buffer;
+            if let Some(index_buffer) = replacement.index_buffer {
+                self.index_buffer = index_buffer;
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// A replacement is still uploading.
+    pub fn is_replacing(&self) -> bool {
+        !self.replacements.is_empty()
+    }
+
+    pub fn vertex_buffer(&self) -> vk::Buffer {
+        self.vertex_buffer.handle()
+    }
+
+    pub fn index_buffer(&self) -> vk::Buffer {
+        self.index_buffer.handle()
+    }
+
+    pub fn index_type(&self) -> vk::IndexType {
+        self.index_buffer.index_type()
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_buffer.len() as u32
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_buffer.len() as u32
+    }
+
+    /// Render queue entry drawing the whole mesh, `depth` is the distance to the camera used for sorting.
+    pub fn draw_item(&self, pipeline: vk::Pipeline, depth: f32) -> DrawItem {
+        DrawItem {
+            pipeline,
+            vertex_buffer: self.vertex_buffer(),
+            index_buffer: self.index_buffer(),
+            index_type: self.index_type(),
+            index_count: self.index_count(),
+            texture: None,
+            depth,
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::ptr;
+
+use ash::vk::{self, StructureType};
+
+/// How the levels below mip 0 of a texture are filled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipGeneration {
+    /// a single level
+    None,
+    /// linear `cmd_blit_image` from each level to the next, on the graphics family
+    Blit,
+    /// box filtered on the cpu and uploaded with mip 0, for formats without linear blit support
+    Cpu,
+}
+
+impl MipGeneration {
+    /// Blit when the format supports linear filtered blits with optimal tiling, Cpu otherwise. Without
+    /// SAMPLED_IMAGE_FILTER_LINEAR the texture also needs a nearest sampler, see
+    /// `SamplerDescription::with_linear_filter_support`.
+    pub unsafe fn for_format(instance: &ash::Instance, physical_device: vk::PhysicalDevice, format: vk::Format) -> Self {
+        let properties = instance.get_physical_device_format_properties(physical_device, format);
+        let needed = vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        if properties.optimal_tiling_features.contains(needed) {
+            MipGeneration::Blit
+        } else {
+            MipGeneration::Cpu
+        }
+    }
+
+    /// Number of levels an image of this size gets.
+    pub fn levels(&self, width: u32, height: u32) -> u32 {
+        match self {
+            MipGeneration::None => 1,
+            MipGeneration::Blit | MipGeneration::Cpu => mip_levels(width, height),
+        }
+    }
+}
+
+/// Levels of a full chain down to 1x1.
+pub fn mip_levels(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Extent of mip `level`, never below 1.
+pub fn mip_extent(extent: vk::Extent3D, level: u32) -> vk::Extent3D {
+    vk::Extent3D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+        depth: (extent.depth >> level).max(1),
+    }
+}
+
+/// Records blits filling mips 1.. of a color image from mip 0, the queue has to support graphics.
+///
+/// Every level has to be in TRANSFER_DST_OPTIMAL with mip 0 written by a transfer, all of them end
+/// up in `final_layout` and visible to later commands.
+pub unsafe fn cmd_generate_mipmaps(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent3D,
+    levels: u32,
+    final_layout: vk::ImageLayout,
+) {
+    let barrier = |level, old_layout, new_layout, src_access_mask, dst_access_mask| vk::ImageMemoryBarrier {
+        s_type: StructureType::IMAGE_MEMORY_BARRIER,
+        p_next: ptr::null(),
+        src_access_mask,
+        dst_access_mask,
+        old_layout,
+        new_layout,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: level,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+    };
+    let layers = |level| vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: level,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let corner = |extent: vk::Extent3D| vk::Offset3D {
+        x: extent.width as i32,
+        y: extent.height as i32,
+        z: extent.depth as i32,
+    };
+
+    for level in 1..levels {
+        // the previous level is complete, read it for this one
+        let to_source = barrier(
+            level - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_source],
+        );
+
+        let blit = vk::ImageBlit {
+            src_subresource: layers(level - 1),
+            src_offsets: [vk::Offset3D::default(), corner(mip_extent(extent, level - 1))],
+            dst_subresource: layers(level),
+            dst_offsets: [vk::Offset3D::default(), corner(mip_extent(extent, level))],
+        };
+        device.cmd_blit_image(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+
+        let to_final = barrier(
+            level - 1,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            final_layout,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::AccessFlags::MEMORY_READ,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_final],
+        );
+    }
+
+    // the last level is only ever written
+    let last = barrier(
+        levels - 1,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        final_layout,
+        vk::AccessFlags::TRANSFER_WRITE,
+        vk::AccessFlags::MEMORY_READ,
+    );
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[last],
+    );
+}
+
+/// Filters 8 bit rgba `pixels` down to 1x1 the way a linear `cmd_blit_image` does, returns mips 1.. tightly
+/// packed. Each texel bilinearly samples the level above at its scaled center, a 2x2 box for even sizes.
+/// With odd sizes the sample positions spread evenly over the level, so the image does not shift. With
+/// `srgb` the texels are averaged in linear space.
+pub fn generate_mipmaps_cpu(width: u32, height: u32, pixels: &[u8], srgb: bool) -> Vec<Vec<u8>> {
+    let to_linear = |value: u8| {
+        let value = value as f32 / 255.0;
+        if !srgb {
+            value
+        } else if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let from_linear = |value: f32| {
+        let value = if !srgb {
+            value
+        } else if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        };
+        (value * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+
+    let mut levels: Vec<Vec<u8>> = vec![];
+    let (mut src_width, mut src_height) = (width as usize, height as usize);
+    while src_width > 1 || src_height > 1 {
+        let src = levels.last().map_or(pixels, |level| level.as_slice());
+        let (dst_width, dst_height) = ((src_width / 2).max(1), (src_height / 2).max(1));
+        let mut dst = vec![0; dst_width * dst_height * 4];
+        for y in 0..dst_height {
+            let rows = taps(y, src_height, dst_height);
+            for x in 0..dst_width {
+                let columns = taps(x, src_width, dst_width);
+                for channel in 0..4 {
+                    let mut average = 0.0;
+                    for (row, row_weight) in rows {
+                        for (column, column_weight) in columns {
+                            let value = src[(row * src_width + column) * 4 + channel];
+                            // alpha is always linear
+                            let value = if channel == 3 {
+                                value as f32 / 255.0
+                            } else {
+                                to_linear(value)
+                            };
+                            average += value * row_weight * column_weight;
+                        }
+                    }
+                    dst[(y * dst_width + x) * 4 + channel] = if channel == 3 {
+                        (average * 255.0).round() as u8
+                    } else {
+                        from_linear(average)
+                    };
+                }
+            }
+        }
+        levels.push(dst);
+        (src_width, src_height) = (dst_width, dst_height);
+    }
+    levels
+}
+
+/// The two source texels and their weights a linear filter reads for texel `index` of `dst_size`,
+/// sampling at its center scaled to `src_size` and clamped to the edge.
+fn taps(index: usize, src_size: usize, dst_size: usize) -> [(usize, f32); 2] {
+    let center = ((index as f32 + 0.5) * src_size as f32 / dst_size as f32 - 0.5).max(0.0);
+    let first = (center.floor() as usize).min(src_size - 1);
+    let weight = center - first as f32;
+    [(first, 1.0 - weight), ((first + 1).min(src_size - 1), weight)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent3D {
+        vk::Extent3D { width, height, depth: 1 }
+    }
+
+    /// `width` x `height` texels of one rgba color
+    fn solid(width: u32, height: u32, texel: [u8; 4]) -> Vec<u8> {
+        texel.repeat((width * height) as usize)
+    }
+
+    #[test]
+    fn mip_levels_down_to_one_texel() {
+        assert_eq!(mip_levels(1, 1), 1);
+        assert_eq!(mip_levels(2, 2), 2);
+        assert_eq!(mip_levels(256, 256), 9);
+        assert_eq!(mip_levels(640, 427), 10);
+        assert_eq!(mip_levels(1, 1024), 11);
+        assert_eq!(mip_levels(0, 0), 1);
+    }
+
+    #[test]
+    fn mip_extent_halves_and_clamps() {
+        assert_eq!(mip_extent(extent(640, 427), 0), extent(640, 427));
+        assert_eq!(mip_extent(extent(640, 427), 1), extent(320, 213));
+        assert_eq!(mip_extent(extent(640, 427), 8), extent(2, 1));
+        assert_eq!(mip_extent(extent(640, 427), 9), extent(1, 1));
+        assert_eq!(mip_extent(extent(640, 427), 20), extent(1, 1));
+    }
+
+    #[test]
+    fn cpu_chain_sizes() {
+        for (width, height) in [(4, 4), (5, 3), (7, 1), (1, 6), (640, 427)] {
+            let levels = generate_mipmaps_cpu(width, height, &solid(width, height, [0; 4]), false);
+            assert_eq!(levels.len() as u32, mip_levels(width, height) - 1);
+            for (index, level) in levels.iter().enumerate() {
+                let size = mip_extent(extent(width, height), index as u32 + 1);
+                assert_eq!(level.len(), (size.width * size.height * 4) as usize);
+            }
+        }
+        assert!(generate_mipmaps_cpu(1, 1, &[1, 2, 3, 4], true).is_empty());
+    }
+
+    #[test]
+    fn cpu_box_filter() {
+        // 2x2 of black, white, black, white
+        let pixels = [0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 255, 255, 255, 255];
+        let levels = generate_mipmaps_cpu(2, 2, &pixels, false);
+        assert_eq!(levels, [vec![128, 128, 128, 128]]);
+
+        // averaged in linear space, alpha stays linear
+        let levels = generate_mipmaps_cpu(2, 2, &pixels, true);
+        assert_eq!(levels, [vec![188, 188, 188, 128]]);
+    }
+
+    #[test]
+    fn cpu_odd_sizes_like_a_linear_blit() {
+        // 3 wide down to 1 samples the center of the middle column, like the blit does
+        let pixels = [10, 10, 10, 10, 30, 30, 30, 30, 255, 255, 255, 255];
+        let levels = generate_mipmaps_cpu(3, 1, &pixels, false);
+        assert_eq!(levels, [vec![30, 30, 30, 30]]);
+
+        // 5 wide down to 2 samples at 0.75 and 3.25, the last column counts towards the second texel
+        let pixels: Vec<u8> = [0, 40, 80, 120, 200].iter().flat_map(|&value| [value; 4]).collect();
+        let levels = generate_mipmaps_cpu(5, 1, &pixels, false);
+        assert_eq!(levels[0], [30, 30, 30, 30, 140, 140, 140, 140]);
+
+        // a single column is averaged with itself
+        let pixels = [0, 0, 0, 0, 100, 100, 100, 100];
+        let levels = generate_mipmaps_cpu(1, 2, &pixels, false);
+        assert_eq!(levels, [vec![50, 50, 50, 50]]);
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        // a solid color stays the same through the linear conversion and back
+        for value in 0..=255u8 {
+            let levels = generate_mipmaps_cpu(4, 4, &solid(4, 4, [value, value, value, value]), true);
+            for level in levels {
+                assert!(level.chunks(4).all(|texel| texel == [value; 4]), "{} changed", value);
+            }
+        }
+    }
+}
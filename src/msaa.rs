@@ -0,0 +1,127 @@
+use ash::{
+    prelude::VkResult,
+    vk::{self, MemoryPropertyFlags},
+};
+
+use crate::{
+    allocator::{Allocation, MemoryAllocator},
+    buffer::{create_image_view, image_create_info},
+    device::DeviceFeatures,
+};
+
+/// Multisampling settings, `samples` TYPE_1 turns it off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MsaaConfig {
+    pub samples: vk::SampleCountFlags,
+    /// minimum fraction of samples that get shaded individually, needs the sampleRateShading feature
+    pub sample_shading: Option<f32>,
+}
+
+impl Default for MsaaConfig {
+    fn default() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            sample_shading: None,
+        }
+    }
+}
+
+impl MsaaConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+
+    /// Drops whatever the device can not do, the sample count is lowered to `max_samples`. A count that is not
+    /// a single power of two bit is rounded down to its highest bit first.
+    pub fn clamped(mut self, max_samples: vk::SampleCountFlags, features: &DeviceFeatures) -> Self {
+        let raw = self.samples.as_raw().max(1);
+        if !raw.is_power_of_two() {
+            self.samples = vk::SampleCountFlags::from_raw(1 << (31 - raw.leading_zeros()));
+        }
+        if self.samples.as_raw() > max_samples.as_raw() {
+            self.samples = max_samples;
+        }
+        if !features.sample_rate_shading || !self.is_enabled() {
+            self.sample_shading = None;
+        }
+        self
+    }
+
+    /// The next sample count up to `max_samples`, wrapping around to TYPE_1.
+    pub fn next_sample_count(&self, max_samples: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        let next = vk::SampleCountFlags::from_raw(self.samples.as_raw() << 1);
+        if next.as_raw() > max_samples.as_raw() {
+            vk::SampleCountFlags::TYPE_1
+        } else {
+            next
+        }
+    }
+}
+
+/// Highest sample count usable for both color and depth attachments.
+pub unsafe fn max_usable_sample_count(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> vk::SampleCountFlags {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let counts = properties.limits.framebuffer_color_sample_counts & properties.limits.framebuffer_depth_sample_counts;
+
+    for samples in [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ] {
+        if counts.contains(samples) {
+            return samples;
+        }
+    }
+    vk::SampleCountFlags::TYPE_1
+}
+
+/// Multisampled color attachment that gets resolved into the swapchain image, only lives on the gpu.
+#[derive(Default)]
+pub struct ColorTarget {
+    pub image: vk::Image,
+    pub allocation: Allocation,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl ColorTarget {
+    pub unsafe fn new(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> VkResult<Self> {
+        let image_info = image_create_info(
+            extent.width,
+            extent.height,
+            format,
+            samples,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        );
+        let (image, allocation) = allocator.create_image(device, &image_info, MemoryPropertyFlags::DEVICE_LOCAL)?;
+        let view = create_image_view(device, image, format, vk::ImageAspectFlags::COLOR, 1)?;
+
+        Ok(Self {
+            image,
+            allocation,
+            view,
+            format,
+            samples,
+        })
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device, allocator: &mut MemoryAllocator) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        allocator.free(device, self.allocation);
+    }
+}
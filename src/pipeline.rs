@@ -1,24 +1,442 @@
-use std::ptr;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    mem::size_of,
+    ptr,
+};
 
 use ash::vk::{self, StructureType};
 
-use crate::{constant::Vertex, utility};
-use anyhow::Result;
+use crate::{
+    blend::BlendPreset,
+    constant::Vertex,
+    depth::DepthConfig,
+    msaa::MsaaConfig,
+    push_constant::{push_constant_range, PushConstants},
+    render_pass::{has_stencil_component, RenderPassDescription},
+    utility,
+};
+use anyhow::{Error, Result};
+
+/// A single specialization constant, every variant is 4 bytes like the glsl scalar it maps to.
+#[derive(Clone, Copy, Debug)]
+pub enum SpecializationValue {
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+}
+
+impl SpecializationValue {
+    /// Raw bits as the shader reads them, bools are VkBool32.
+    pub(crate) fn to_bits(self) -> u32 {
+        match self {
+            SpecializationValue::Bool(x) => x as u32,
+            SpecializationValue::Int(x) => x as u32,
+            SpecializationValue::UInt(x) => x,
+            SpecializationValue::Float(x) => x.to_bits(),
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            SpecializationValue::Bool(_) => 0,
+            SpecializationValue::Int(_) => 1,
+            SpecializationValue::UInt(_) => 2,
+            SpecializationValue::Float(_) => 3,
+        }
+    }
+}
+
+// compared by bits, so floats can be used as cache keys
+impl PartialEq for SpecializationValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind() && self.to_bits() == other.to_bits()
+    }
+}
+
+impl Eq for SpecializationValue {}
+
+impl Hash for SpecializationValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind().hash(state);
+        self.to_bits().hash(state);
+    }
+}
+
+/// Specialization constants keyed by `constant_id`, names can be declared to refer to an id.
+#[derive(Clone, Debug, Default)]
+pub struct SpecializationConstants {
+    names: BTreeMap<String, u32>,
+    values: BTreeMap<u32, SpecializationValue>,
+}
+
+// names are only a convenience, the pipeline is the same for the same values
+impl PartialEq for SpecializationConstants {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl Eq for SpecializationConstants {}
+
+impl Hash for SpecializationConstants {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.values.hash(state);
+    }
+}
+
+impl SpecializationConstants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds a name to the `layout(constant_id = id)` used in the shader.
+    pub fn declare(mut self, name: &str, id: u32) -> Self {
+        self.names.insert(name.to_owned(), id);
+        self
+    }
+
+    pub fn with(mut self, id: u32, value: SpecializationValue) -> Self {
+        self.set(id, value);
+        self
+    }
+
+    pub fn with_named(mut self, name: &str, value: SpecializationValue) -> Result<Self> {
+        self.set_named(name, value)?;
+        Ok(self)
+    }
+
+    pub fn set(&mut self, id: u32, value: SpecializationValue) {
+        self.values.insert(id, value);
+    }
+
+    pub fn set_named(&mut self, name: &str, value: SpecializationValue) -> Result<()> {
+        let id = *self
+            .names
+            .get(name)
+            .ok_or_else(|| Error::msg(format!("specialization constant {} is not declared", name)))?;
+        self.set(id, value);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u32) -> Option<SpecializationValue> {
+        self.values.get(&id).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Packs the values and generates the map entries, one 4 byte slot per constant.
+    pub fn build(&self) -> SpecializationData {
+        let mut entries = vec![];
+        let mut data = vec![];
+        for (id, value) in self.values.iter() {
+            entries.push(vk::SpecializationMapEntry {
+                constant_id: *id,
+                offset: data.len() as u32,
+                size: size_of::<u32>(),
+            });
+            data.extend_from_slice(&value.to_bits().to_ne_bytes());
+        }
+        SpecializationData { entries, data }
+    }
+}
+
+/// Backing storage for a `vk::SpecializationInfo`, has to outlive the pipeline creation.
+pub struct SpecializationData {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationData {
+    pub fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo {
+            map_entry_count: self.entries.len() as u32,
+            p_map_entries: self.entries.as_ptr(),
+            data_size: self.data.len(),
+            p_data: self.data.as_ptr() as *const std::ffi::c_void,
+        }
+    }
+}
+
+/// Everything needed to build a graphics pipeline.
+#[derive(Clone, Debug)]
+pub struct PipelineDescription {
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    /// shared by every stage, ids a stage does not use are ignored
+    pub constants: SpecializationConstants,
+    /// depth testing, needs a depth attachment in the target
+    pub depth: Option<DepthConfig>,
+    /// has to match the sample count of the attachments
+    pub msaa: MsaaConfig,
+    /// blending of every color attachment without an entry in `attachment_blends`
+    pub blend: BlendPreset,
+    /// per color attachment blending, indexed like the subpass color attachments
+    pub attachment_blends: Vec<BlendPreset>,
+    /// LINE and POINT need the fillModeNonSolid feature
+    pub polygon_mode: vk::PolygonMode,
+    /// push constant blocks of the layout, see `with_push_constants`
+    pub push_constants: Vec<vk::PushConstantRange>,
+    /// `set_layouts[i]` is `layout(set = i)` in the shaders
+    pub set_layouts: Vec<vk::DescriptorSetLayout>,
+}
+
+impl Default for PipelineDescription {
+    fn default() -> Self {
+        Self {
+            vertex_shader: "shaders/spv/vert.spv".to_owned(),
+            fragment_shader: "shaders/spv/frag.spv".to_owned(),
+            constants: SpecializationConstants::default(),
+            depth: None,
+            msaa: MsaaConfig::default(),
+            blend: BlendPreset::Opaque,
+            attachment_blends: vec![],
+            polygon_mode: vk::PolygonMode::FILL,
+            push_constants: vec![],
+            set_layouts: vec![],
+        }
+    }
+}
+
+impl PipelineDescription {
+    /// Blends every color attachment with `preset`, blended geometry keeps testing depth but stops writing it.
+    pub fn with_blend(mut self, preset: BlendPreset) -> Self {
+        self.blend = preset;
+        if preset.is_transparent() {
+            self.depth = self.depth.map(|depth| DepthConfig { write: false, ..depth });
+        }
+        self
+    }
+
+    pub fn with_attachment_blend(mut self, attachment: usize, preset: BlendPreset) -> Self {
+        if self.attachment_blends.len() <= attachment {
+            self.attachment_blends.resize(attachment + 1, self.blend);
+        }
+        self.attachment_blends[attachment] = preset;
+        self
+    }
+
+    pub fn attachment_blend(&self, attachment: usize) -> BlendPreset {
+        self.attachment_blends.get(attachment).copied().unwrap_or(self.blend)
+    }
+
+    /// Adds the push constant block `T` to the layout, fails if it does not fit the device limits.
+    pub fn with_push_constants<T: PushConstants>(mut self, limits: &vk::PhysicalDeviceLimits) -> Result<Self> {
+        self.push_constants.push(push_constant_range::<T>(limits)?);
+        Ok(self)
+    }
+
+    /// True if any color attachment reads what is already there.
+    pub fn is_transparent(&self) -> bool {
+        self.blend.is_transparent() || self.attachment_blends.iter().any(|blend| blend.is_transparent())
+    }
+}
+
+/// What a graphics pipeline renders into.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PipelineTarget {
+    RenderPass {
+        render_pass: vk::RenderPass,
+        subpass: u32,
+        /// color attachments of the subpass, every one needs a blend state
+        color_attachments: u32,
+    },
+    /// `cmd_begin_rendering`, the formats have to match the attachments used while recording
+    Dynamic {
+        color_formats: Vec<vk::Format>,
+        depth_format: vk::Format,
+        stencil_format: vk::Format,
+    },
+}
+
+impl PipelineTarget {
+    /// `description` is the one `render_pass` was created from.
+    pub fn render_pass(render_pass: vk::RenderPass, description: &RenderPassDescription, subpass: u32) -> Self {
+        PipelineTarget::RenderPass {
+            render_pass,
+            subpass,
+            color_attachments: description.subpasses[subpass as usize].colors.len() as u32,
+        }
+    }
+
+    pub fn dynamic(color_format: vk::Format) -> Self {
+        PipelineTarget::Dynamic {
+            color_formats: vec![color_format],
+            depth_format: vk::Format::UNDEFINED,
+            stencil_format: vk::Format::UNDEFINED,
+        }
+    }
+
+    /// Depth attachment format for dynamic rendering, also used as the stencil format if it has a stencil component.
+    pub fn with_depth_format(mut self, format: vk::Format) -> Self {
+        if let PipelineTarget::Dynamic {
+            depth_format,
+            stencil_format,
+            ..
+        } = &mut self
+        {
+            *depth_format = format;
+            if has_stencil_component(format) {
+                *stencil_format = format;
+            }
+        }
+        self
+    }
+}
+
+/// The parts of a description a variant may change, shaders and layout always come from the base.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct VariantKey {
+    constants: SpecializationConstants,
+    polygon_mode: vk::PolygonMode,
+    blend: BlendPreset,
+    attachment_blends: Vec<BlendPreset>,
+    depth: Option<DepthConfig>,
+}
+
+impl VariantKey {
+    fn new(description: &PipelineDescription) -> Self {
+        Self {
+            constants: description.constants.clone(),
+            polygon_mode: description.polygon_mode,
+            blend: description.blend,
+            attachment_blends: description.attachment_blends.clone(),
+            depth: description.depth,
+        }
+    }
+}
+
+/// Pipelines that only differ by specialization constants and a little fixed function state, they share
+/// the layout and are created on first use.
+pub struct PipelineVariants {
+    pub description: PipelineDescription,
+    pub layout: vk::PipelineLayout,
+    target: PipelineTarget,
+    swapchain_extent: vk::Extent2D,
+    variants: HashMap<VariantKey, vk::Pipeline>,
+}
+
+impl PipelineVariants {
+    pub unsafe fn new(
+        device: &ash::Device,
+        description: PipelineDescription,
+        swapchain_extent: vk::Extent2D,
+        target: PipelineTarget,
+    ) -> Result<Self> {
+        let layout = create_layout(device, &description)?;
+        Ok(Self {
+            description,
+            layout,
+            target,
+            swapchain_extent,
+            variants: HashMap::new(),
+        })
+    }
+
+    /// Pipeline using the description's constants.
+    pub unsafe fn base(&mut self, device: &ash::Device) -> Result<vk::Pipeline> {
+        let constants = self.description.constants.clone();
+        self.get(device, &constants)
+    }
+
+    /// Pipeline with `constants` applied on top of the description's, cached per combination.
+    pub unsafe fn get(&mut self, device: &ash::Device, constants: &SpecializationConstants) -> Result<vk::Pipeline> {
+        let mut description = self.description.clone();
+        for (id, value) in constants.values.iter() {
+            description.constants.set(*id, *value);
+        }
+        self.variant(device, &description)
+    }
+
+    /// Pipeline for `description`, a copy of the base with other constants, polygon mode, blending or depth
+    /// state. Anything else it changes is ignored.
+    pub unsafe fn variant(&mut self, device: &ash::Device, description: &PipelineDescription) -> Result<vk::Pipeline> {
+        let key = VariantKey::new(description);
+        if let Some(pipeline) = self.variants.get(&key) {
+            return Ok(*pipeline);
+        }
+
+        let description = PipelineDescription {
+            constants: key.constants.clone(),
+            polygon_mode: key.polygon_mode,
+            blend: key.blend,
+            attachment_blends: key.attachment_blends.clone(),
+            depth: key.depth,
+            ..self.description.clone()
+        };
+        let pipeline = create_graphics_pipeline(device, &description, self.layout, self.swapchain_extent, &self.target)?;
+        self.variants.insert(key, pipeline);
+        Ok(pipeline)
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for (_, pipeline) in self.variants.drain() {
+            device.destroy_pipeline(pipeline, None);
+        }
+        device.destroy_pipeline_layout(self.layout, None);
+    }
+}
 
 pub unsafe fn create_pipeline_layout(
     device: &ash::Device,
     swapchain_extent: vk::Extent2D,
-    render_pass: vk::RenderPass,
+    target: &PipelineTarget,
+    description: &PipelineDescription,
 ) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
-    let frag_bytes = utility::read_file("shaders/spv/frag.spv")?;
-    let vert_bytes = utility::read_file("shaders/spv/vert.spv")?;
+    let layout = create_layout(device, description)?;
+    match create_graphics_pipeline(device, description, layout, swapchain_extent, target) {
+        Ok(pipeline) => Ok((pipeline, layout)),
+        Err(e) => {
+            device.destroy_pipeline_layout(layout, None);
+            Err(e)
+        }
+    }
+}
+
+pub(crate) unsafe fn create_layout(device: &ash::Device, description: &PipelineDescription) -> Result<vk::PipelineLayout> {
+    let mut pipeline_layout_info = vk::PipelineLayoutCreateInfo::default();
+    pipeline_layout_info.s_type = vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO;
+    pipeline_layout_info.set_layout_count = description.set_layouts.len() as u32;
+    pipeline_layout_info.p_set_layouts = description.set_layouts.as_ptr();
+    pipeline_layout_info.push_constant_range_count = description.push_constants.len() as u32;
+    pipeline_layout_info.p_push_constant_ranges = description.push_constants.as_ptr();
+
+    Ok(device.create_pipeline_layout(&pipeline_layout_info, None)?)
+}
+
+pub unsafe fn create_graphics_pipeline(
+    device: &ash::Device,
+    description: &PipelineDescription,
+    layout: vk::PipelineLayout,
+    swapchain_extent: vk::Extent2D,
+    target: &PipelineTarget,
+) -> Result<vk::Pipeline> {
+    let frag_bytes = utility::read_file(&description.fragment_shader)?;
+    let vert_bytes = utility::read_file(&description.vertex_shader)?;
 
     let vert_shader = create_shader_module(device, vert_bytes)?;
-    let frag_shader = create_shader_module(device, frag_bytes)?;
+    let frag_shader = match create_shader_module(device, frag_bytes) {
+        Ok(frag_shader) => frag_shader,
+        Err(e) => {
+            device.destroy_shader_module(vert_shader, None);
+            return Err(e);
+        }
+    };
 
     let entry_point_name = std::ffi::CString::new("main").expect("CString::new failed");
 
-    let mut shader_stages = [
+    let specialization_data = description.constants.build();
+    let specialization_info = specialization_data.info();
+    let p_specialization_info = if description.constants.is_empty() {
+        ptr::null()
+    } else {
+        &specialization_info as *const vk::SpecializationInfo
+    };
+
+    let shader_stages = [
         vk::PipelineShaderStageCreateInfo {
             s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
             p_next: ptr::null(),
@@ -26,7 +444,7 @@ pub unsafe fn create_pipeline_layout(
             stage: vk::ShaderStageFlags::VERTEX,
             module: vert_shader,
             p_name: entry_point_name.as_ptr(),
-            p_specialization_info: ptr::null(),
+            p_specialization_info,
         },
         vk::PipelineShaderStageCreateInfo {
             s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
@@ -35,7 +453,7 @@ pub unsafe fn create_pipeline_layout(
             stage: vk::ShaderStageFlags::FRAGMENT,
             module: frag_shader,
             p_name: entry_point_name.as_ptr(),
-            p_specialization_info: ptr::null(),
+            p_specialization_info,
         },
     ];
 
@@ -98,13 +516,14 @@ pub unsafe fn create_pipeline_layout(
     rasterizer.s_type = vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO;
     rasterizer.depth_clamp_enable = vk::FALSE;
     rasterizer.rasterizer_discard_enable = vk::FALSE;
-    // fills the primitive triangle
-    rasterizer.polygon_mode = vk::PolygonMode::FILL;
+    // fills the primitive triangle, or only draws its edges or corners
+    rasterizer.polygon_mode = description.polygon_mode;
     rasterizer.line_width = 1.0;
 
     // face is forward or whatever
     rasterizer.cull_mode = vk::CullModeFlags::BACK;
-    rasterizer.front_face = vk::FrontFace::CLOCKWISE;
+    // the projection flips y, which flips the winding as well
+    rasterizer.front_face = vk::FrontFace::COUNTER_CLOCKWISE;
 
     // can be used for shadow mapping
     rasterizer.depth_bias_enable = vk::FALSE;
@@ -114,45 +533,32 @@ pub unsafe fn create_pipeline_layout(
 
     let mut multi_sampling = vk::PipelineMultisampleStateCreateInfo::default();
     multi_sampling.s_type = vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO;
-    multi_sampling.sample_shading_enable = vk::FALSE;
-    multi_sampling.rasterization_samples = vk::SampleCountFlags::TYPE_1;
-    multi_sampling.min_sample_shading = 1.0;
+    multi_sampling.sample_shading_enable = description.msaa.sample_shading.is_some() as vk::Bool32;
+    multi_sampling.rasterization_samples = description.msaa.samples;
+    multi_sampling.min_sample_shading = description.msaa.sample_shading.unwrap_or(1.0);
     multi_sampling.p_sample_mask = std::ptr::null();
     multi_sampling.alpha_to_coverage_enable = vk::FALSE;
     multi_sampling.alpha_to_one_enable = vk::FALSE;
 
-    let mut color_blend_attachment = vk::PipelineColorBlendAttachmentState::default();
-    color_blend_attachment.color_write_mask =
-        vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A;
-    color_blend_attachment.blend_enable = vk::FALSE;
-    color_blend_attachment.src_color_blend_factor = vk::BlendFactor::ONE;
-    color_blend_attachment.dst_color_blend_factor = vk::BlendFactor::ZERO;
-    color_blend_attachment.src_alpha_blend_factor = vk::BlendFactor::ONE;
-    color_blend_attachment.dst_alpha_blend_factor = vk::BlendFactor::ZERO;
-
-    color_blend_attachment.color_blend_op = vk::BlendOp::ADD;
-    color_blend_attachment.alpha_blend_op = vk::BlendOp::ADD;
+    let color_attachment_count = match target {
+        PipelineTarget::RenderPass { color_attachments, .. } => *color_attachments as usize,
+        PipelineTarget::Dynamic { color_formats, .. } => color_formats.len(),
+    };
+    let color_blend_attachments: Vec<_> = (0..color_attachment_count)
+        .map(|attachment| description.attachment_blend(attachment).attachment_state())
+        .collect();
 
     let mut color_blending = vk::PipelineColorBlendStateCreateInfo::default();
     color_blending.s_type = vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO;
     color_blending.logic_op_enable = vk::FALSE;
     color_blending.logic_op = vk::LogicOp::COPY;
-    color_blending.attachment_count = 1;
-    color_blending.p_attachments = &color_blend_attachment;
+    color_blending.attachment_count = color_blend_attachments.len() as u32;
+    color_blending.p_attachments = color_blend_attachments.as_ptr();
     color_blending.blend_constants[0] = 0.0;
     color_blending.blend_constants[1] = 0.0;
     color_blending.blend_constants[2] = 0.0;
     color_blending.blend_constants[3] = 0.0;
 
-    let mut pipeline_layout_info = vk::PipelineLayoutCreateInfo::default();
-    pipeline_layout_info.s_type = vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO;
-    pipeline_layout_info.set_layout_count = 0;
-    pipeline_layout_info.p_set_layouts = std::ptr::null();
-    pipeline_layout_info.push_constant_range_count = 0;
-    pipeline_layout_info.p_push_constant_ranges = std::ptr::null();
-
-    let layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
-
     let mut info = vk::GraphicsPipelineCreateInfo::default();
     info.s_type = vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO;
 
@@ -162,27 +568,50 @@ pub unsafe fn create_pipeline_layout(
     info.p_viewport_state = &view_state;
     info.p_rasterization_state = &rasterizer;
     info.p_multisample_state = &multi_sampling;
-    info.p_depth_stencil_state = std::ptr::null();
+    let depth_stencil = description.depth.map(|depth| depth.depth_stencil_state());
+    info.p_depth_stencil_state = match &depth_stencil {
+        Some(depth_stencil) => depth_stencil,
+        None => std::ptr::null(),
+    };
     info.p_color_blend_state = &color_blending;
     info.p_dynamic_state = &dynamic_state;
     info.layout = layout;
-    info.render_pass = render_pass;
-    info.subpass = 0;
+
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::default();
+    match target {
+        PipelineTarget::RenderPass {
+            render_pass, subpass, ..
+        } => {
+            info.render_pass = *render_pass;
+            info.subpass = *subpass;
+        }
+        PipelineTarget::Dynamic {
+            color_formats,
+            depth_format,
+            stencil_format,
+        } => {
+            rendering_info.color_attachment_count = color_formats.len() as u32;
+            rendering_info.p_color_attachment_formats = color_formats.as_ptr();
+            rendering_info.depth_attachment_format = *depth_format;
+            rendering_info.stencil_attachment_format = *stencil_format;
+            info.p_next = &rendering_info as *const vk::PipelineRenderingCreateInfo as *const std::ffi::c_void;
+            info.render_pass = vk::RenderPass::null();
+        }
+    }
     info.base_pipeline_handle = vk::Pipeline::null();
     info.base_pipeline_index = -1;
     info.p_input_assembly_state = &input_assembly;
 
-    let pipeline = device
-        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)
-        .expect("Error creating the graphic pipeline");
+    let pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[info], None);
 
     device.destroy_shader_module(vert_shader, None);
     device.destroy_shader_module(frag_shader, None);
 
-    Ok((pipeline[0], layout))
+    let pipeline = pipeline.map_err(|(_, e)| e)?;
+    Ok(pipeline[0])
 }
 
-unsafe fn create_shader_module(device: &ash::Device, bytes: Vec<u8>) -> Result<vk::ShaderModule> {
+pub(crate) unsafe fn create_shader_module(device: &ash::Device, bytes: Vec<u8>) -> Result<vk::ShaderModule> {
     let mut create_info = vk::ShaderModuleCreateInfo::default();
 
     create_info.s_type = vk::StructureType::SHADER_MODULE_CREATE_INFO;
@@ -194,65 +623,3 @@ unsafe fn create_shader_module(device: &ash::Device, bytes: Vec<u8>) -> Result<v
     let shader_module = device.create_shader_module(&create_info, None)?;
     Ok(shader_module)
 }
-
-pub unsafe fn create_render_pass(swapchain_format: vk::Format, device: &ash::Device) -> Result<vk::RenderPass> {
-    let color_attachment = vk::AttachmentDescription {
-        format: swapchain_format,
-        flags: vk::AttachmentDescriptionFlags::empty(),
-        samples: vk::SampleCountFlags::TYPE_1,
-        load_op: vk::AttachmentLoadOp::CLEAR,
-        store_op: vk::AttachmentStoreOp::STORE,
-        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-        initial_layout: vk::ImageLayout::UNDEFINED,
-        final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-    };
-
-    let color_attachment_ref = vk::AttachmentReference {
-        attachment: 0,
-        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-    };
-
-    let subpasses = [vk::SubpassDescription {
-        color_attachment_count: 1,
-        p_color_attachments: &color_attachment_ref,
-        p_depth_stencil_attachment: ptr::null(),
-        flags: vk::SubpassDescriptionFlags::empty(),
-        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-        input_attachment_count: 0,
-        p_input_attachments: ptr::null(),
-        p_resolve_attachments: ptr::null(),
-        preserve_attachment_count: 0,
-        p_preserve_attachments: ptr::null(),
-    }];
-
-    let render_pass_attachments = [color_attachment];
-
-    let subpass_dependencies = [vk::SubpassDependency {
-        src_subpass: vk::SUBPASS_EXTERNAL,
-        dst_subpass: 0,
-        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        src_access_mask: vk::AccessFlags::empty(),
-        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-        dependency_flags: vk::DependencyFlags::empty(),
-    }];
-
-    let renderpass_create_info = vk::RenderPassCreateInfo {
-        s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
-        flags: vk::RenderPassCreateFlags::empty(),
-        p_next: ptr::null(),
-        attachment_count: render_pass_attachments.len() as u32,
-        p_attachments: render_pass_attachments.as_ptr(),
-        subpass_count: subpasses.len() as u32,
-        p_subpasses: subpasses.as_ptr(),
-        dependency_count: subpass_dependencies.len() as u32,
-        p_dependencies: subpass_dependencies.as_ptr(),
-    };
-
-    unsafe {
-        Ok(device
-            .create_render_pass(&renderpass_create_info, None)
-            .expect("Failed to create render pass!"))
-    }
-}
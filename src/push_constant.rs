@@ -0,0 +1,58 @@
+use std::mem::size_of;
+
+use anyhow::{Error, Result};
+use ash::vk;
+
+/// A `layout(push_constant)` block mirrored by a rust type.
+///
+/// # Safety
+/// The type has to be `#[repr(C)]` with the same layout as the block in the shader (std430 rules),
+/// its bytes are copied as they are.
+pub unsafe trait PushConstants: Copy + 'static {
+    /// stages that read the block
+    const STAGES: vk::ShaderStageFlags;
+    /// byte offset of the block, when several blocks share a layout
+    const OFFSET: u32 = 0;
+
+    /// Range for the pipeline layout, not checked against the device limits.
+    fn range() -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags: Self::STAGES,
+            offset: Self::OFFSET,
+            size: size_of::<Self>() as u32,
+        }
+    }
+}
+
+/// Range of `T`, checked to be 4 byte aligned and to fit in `maxPushConstantsSize`.
+pub fn push_constant_range<T: PushConstants>(limits: &vk::PhysicalDeviceLimits) -> Result<vk::PushConstantRange> {
+    let range = T::range();
+    if range.size == 0 || range.size % 4 != 0 || range.offset % 4 != 0 {
+        return Err(Error::msg(format!(
+            "push constant block {} has to have a non zero size and offset that are multiples of 4, got size {} offset {}",
+            std::any::type_name::<T>(),
+            range.size,
+            range.offset
+        )));
+    }
+    if range.offset + range.size > limits.max_push_constants_size {
+        return Err(Error::msg(format!(
+            "push constant block {} ends at {} bytes, the device allows {}",
+            std::any::type_name::<T>(),
+            range.offset + range.size,
+            limits.max_push_constants_size
+        )));
+    }
+    Ok(range)
+}
+
+/// Pushes `value` for the stages it was declared for, `layout` has to include `T::range()`.
+pub unsafe fn cmd_push_constants<T: PushConstants>(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    value: &T,
+) {
+    let bytes = std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>());
+    device.cmd_push_constants(command_buffer, layout, T::STAGES, T::OFFSET, bytes);
+}
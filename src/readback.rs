@@ -0,0 +1,281 @@
+use std::{cell::RefCell, marker::PhantomData, mem::size_of, ptr, rc::Rc};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::{
+    allocator::{Allocation, MemoryAllocator},
+    gpu_buffer::Buffer,
+    memory::MemoryProperties,
+};
+
+/// What `ReadbackContext::read_buffer` copies.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferRead<'a> {
+    /// needs TRANSFER_SRC usage
+    pub src: vk::Buffer,
+    /// in bytes
+    pub offset: vk::DeviceSize,
+    /// elements of the read type
+    pub count: usize,
+    pub wait_semaphores: &'a [(vk::Semaphore, vk::PipelineStageFlags)],
+}
+
+/// Everything one copy to the host owns.
+struct Staging {
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+}
+
+impl Staging {
+    /// The copy has to be done.
+    unsafe fn read<T: Copy>(&self, device: &ash::Device, allocator: &MemoryAllocator, count: usize) -> VkResult<Vec<T>> {
+        // the allocator keeps host visible memory mapped, only non coherent memory needs the invalidate
+        allocator.invalidate(device, &self.allocation, 0, self.allocation.size)?;
+        let pointer = self.allocation.mapped.expect("host visible memory is mapped");
+        Ok(std::slice::from_raw_parts(pointer.as_ptr() as *const T, count).to_vec())
+    }
+
+    /// The copy has to be done or never submitted.
+    unsafe fn destroy(self, device: &ash::Device, command_pool: vk::CommandPool, allocator: &mut MemoryAllocator) {
+        if self.command_buffer != vk::CommandBuffer::null() {
+            device.free_command_buffers(command_pool, &[self.command_buffer]);
+        }
+        device.destroy_fence(self.fence, None);
+        device.destroy_buffer(self.buffer, None);
+        allocator.free(device, self.allocation);
+    }
+}
+
+/// Copies device buffers back to the host, on a queue of the family that owns them.
+pub struct ReadbackContext {
+    pub queue: vk::Queue,
+    pub queue_family: u32,
+    command_pool: vk::CommandPool,
+    /// dropped readbacks, freed once their copy finished
+    abandoned: Rc<RefCell<Vec<Staging>>>,
+}
+
+impl ReadbackContext {
+    pub unsafe fn new(device: &ash::Device, queue_family: u32) -> VkResult<Self> {
+        let queue = device.get_device_queue(queue_family, 0);
+        let pool_info = vk::CommandPoolCreateInfo {
+            s_type: StructureType::COMMAND_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::CommandPoolCreateFlags::TRANSIENT,
+            queue_family_index: queue_family,
+        };
+        let command_pool = device.create_command_pool(&pool_info, None)?;
+
+        Ok(Self {
+            queue,
+            queue_family,
+            command_pool,
+            abandoned: Rc::default(),
+        })
+    }
+
+    /// Starts copying `read.count` elements of `T` into host memory, after `read.wait_semaphores`.
+    /// Earlier writes to `read.src` on this queue are made visible to the copy.
+    pub unsafe fn read_buffer<T: Copy>(
+        &self,
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        read: &BufferRead,
+    ) -> VkResult<Readback<T>> {
+        self.free_abandoned(device, allocator)?;
+
+        let size = (read.count.max(1) * size_of::<T>()) as vk::DeviceSize;
+        // cached memory makes the cpu reads fast, it may not be coherent though
+        let (buffer, allocation) =
+            allocator.create_buffer(device, size, vk::BufferUsageFlags::TRANSFER_DST, MemoryProperties::readback())?;
+        let mut staging = Staging {
+            command_buffer: vk::CommandBuffer::null(),
+            fence: vk::Fence::null(),
+            buffer,
+            allocation,
+        };
+
+        if let Err(e) = self.submit::<T>(device, &mut staging, read) {
+            // nothing was submitted, everything can go right away
+            staging.destroy(device, self.command_pool, allocator);
+            return Err(e);
+        }
+
+        Ok(Readback {
+            staging: Some(staging),
+            command_pool: self.command_pool,
+            abandoned: self.abandoned.clone(),
+            count: read.count,
+            marker: PhantomData,
+        })
+    }
+
+    /// Records and submits the copy into `staging`, the handles are stored as soon as they exist.
+    unsafe fn submit<T: Copy>(&self, device: &ash::Device, staging: &mut Staging, read: &BufferRead) -> VkResult<()> {
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            command_pool: self.command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: 1,
+        };
+        let command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
+        staging.command_buffer = command_buffer;
+        let begin_info = vk::CommandBufferBeginInfo {
+            s_type: StructureType::COMMAND_BUFFER_BEGIN_INFO,
+            p_next: ptr::null(),
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            p_inheritance_info: ptr::null(),
+        };
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+
+        let before = vk::MemoryBarrier {
+            s_type: StructureType::MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[before],
+            &[],
+            &[],
+        );
+        let region = vk::BufferCopy {
+            src_offset: read.offset,
+            dst_offset: 0,
+            size: (read.count * size_of::<T>()) as vk::DeviceSize,
+        };
+        if read.count > 0 {
+            device.cmd_copy_buffer(command_buffer, read.src, staging.buffer, &[region]);
+        }
+        let after = vk::MemoryBarrier {
+            s_type: StructureType::MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[after],
+            &[],
+            &[],
+        );
+        device.end_command_buffer(command_buffer)?;
+
+        let fence_info = vk::FenceCreateInfo {
+            s_type: StructureType::FENCE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::FenceCreateFlags::empty(),
+        };
+        staging.fence = device.create_fence(&fence_info, None)?;
+
+        let (waits, wait_stages): (Vec<vk::Semaphore>, Vec<vk::PipelineStageFlags>) =
+            read.wait_semaphores.iter().copied().unzip();
+        let submit_info = [vk::SubmitInfo {
+            s_type: StructureType::SUBMIT_INFO,
+            p_next: ptr::null(),
+            wait_semaphore_count: waits.len() as u32,
+            p_wait_semaphores: waits.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: 1,
+            p_command_buffers: &command_buffer,
+            signal_semaphore_count: 0,
+            p_signal_semaphores: ptr::null(),
+        }];
+        device.queue_submit(self.queue, &submit_info, staging.fence)
+    }
+
+    /// Frees the dropped readbacks whose copy finished.
+    unsafe fn free_abandoned(&self, device: &ash::Device, allocator: &mut MemoryAllocator) -> VkResult<()> {
+        let mut abandoned = self.abandoned.borrow_mut();
+        let mut index = 0;
+        while index < abandoned.len() {
+            if device.get_fence_status(abandoned[index].fence)? {
+                abandoned.swap_remove(index).destroy(device, self.command_pool, allocator);
+            } else {
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the whole buffer and blocks until the data is there.
+    pub unsafe fn read<T: Copy>(
+        &self,
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        src: &Buffer<T>,
+    ) -> VkResult<Vec<T>> {
+        let read = BufferRead {
+            src: src.handle(),
+            offset: 0,
+            count: src.len(),
+            wait_semaphores: &[],
+        };
+        self.read_buffer(device, allocator, &read)?.into_vec(device, allocator)
+    }
+
+    /// Waits for the copies of dropped readbacks, the ones still held have to be taken or dropped first.
+    pub unsafe fn destroy(&mut self, device: &ash::Device, allocator: &mut MemoryAllocator) -> VkResult<()> {
+        for staging in self.abandoned.borrow_mut().drain(..) {
+            device.wait_for_fences(&[staging.fence], true, u64::MAX)?;
+            staging.destroy(device, self.command_pool, allocator);
+        }
+        device.destroy_command_pool(self.command_pool, None);
+        Ok(())
+    }
+}
+
+/// A copy to the host in flight, poll `is_ready` or take the data with `into_vec`.
+/// Dropping it leaves the staging buffer to the context, which frees it once the copy finished.
+pub struct Readback<T: Copy> {
+    /// None once taken by `into_vec`
+    staging: Option<Staging>,
+    command_pool: vk::CommandPool,
+    abandoned: Rc<RefCell<Vec<Staging>>>,
+    count: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T: Copy> Readback<T> {
+    pub fn fence(&self) -> vk::Fence {
+        self.staging.as_ref().map_or(vk::Fence::null(), |staging| staging.fence)
+    }
+
+    pub unsafe fn is_ready(&self, device: &ash::Device) -> VkResult<bool> {
+        device.get_fence_status(self.fence())
+    }
+
+    pub unsafe fn wait(&self, device: &ash::Device) -> VkResult<()> {
+        device.wait_for_fences(&[self.fence()], true, u64::MAX)
+    }
+
+    /// Waits for the copy, returns the elements and frees the staging buffer.
+    pub unsafe fn into_vec(mut self, device: &ash::Device, allocator: &mut MemoryAllocator) -> VkResult<Vec<T>> {
+        self.wait(device)?;
+        let staging = self.staging.take().unwrap();
+        let result = staging.read(device, allocator, self.count);
+        staging.destroy(device, self.command_pool, allocator);
+        result
+    }
+}
+
+impl<T: Copy> Drop for Readback<T> {
+    fn drop(&mut self) {
+        if let Some(staging) = self.staging.take() {
+            self.abandoned.borrow_mut().push(staging);
+        }
+    }
+}
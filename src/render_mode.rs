@@ -0,0 +1,141 @@
+use anyhow::Result;
+use ash::vk;
+
+use crate::{
+    blend::BlendPreset,
+    depth::DepthConfig,
+    device::DeviceFeatures,
+    pipeline::{PipelineDescription, PipelineTarget, PipelineVariants, SpecializationValue},
+};
+
+/// `layout(constant_id)` of DEBUG_VIEW in shader.frag
+pub const DEBUG_VIEW_CONSTANT_ID: u32 = 0;
+
+/// How geometry is drawn, everything but Shaded is meant for inspecting meshes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RenderMode {
+    #[default]
+    Shaded,
+    /// triangle edges only, needs fillModeNonSolid
+    Wireframe,
+    /// triangle corners only, needs fillModeNonSolid
+    Points,
+    Normals,
+    Uvs,
+    VertexColors,
+    /// every fragment adds up without depth testing, bright spots are drawn many times
+    Overdraw,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 7] = [
+        RenderMode::Shaded,
+        RenderMode::Wireframe,
+        RenderMode::Points,
+        RenderMode::Normals,
+        RenderMode::Uvs,
+        RenderMode::VertexColors,
+        RenderMode::Overdraw,
+    ];
+
+    pub fn polygon_mode(&self) -> vk::PolygonMode {
+        match self {
+            RenderMode::Wireframe => vk::PolygonMode::LINE,
+            RenderMode::Points => vk::PolygonMode::POINT,
+            _ => vk::PolygonMode::FILL,
+        }
+    }
+
+    /// Value of the DEBUG_VIEW specialization constant.
+    pub fn debug_view(&self) -> i32 {
+        match self {
+            RenderMode::Shaded | RenderMode::Wireframe | RenderMode::Points => 0,
+            RenderMode::Normals => 1,
+            RenderMode::Uvs => 2,
+            RenderMode::VertexColors => 3,
+            RenderMode::Overdraw => 4,
+        }
+    }
+
+    pub fn is_supported(&self, features: &DeviceFeatures) -> bool {
+        self.polygon_mode() == vk::PolygonMode::FILL || features.fill_mode_non_solid
+    }
+
+    /// The mode itself if the device can draw it, Shaded otherwise. Compare with `self` to tell the user.
+    pub fn or_supported(self, features: &DeviceFeatures) -> Self {
+        if self.is_supported(features) {
+            self
+        } else {
+            RenderMode::Shaded
+        }
+    }
+
+    /// The next mode the device supports, wrapping around.
+    pub fn next(&self, features: &DeviceFeatures) -> Self {
+        let index = Self::ALL.iter().position(|mode| mode == self).unwrap_or(0);
+        (1..=Self::ALL.len())
+            .map(|offset| Self::ALL[(index + offset) % Self::ALL.len()])
+            .find(|mode| mode.is_supported(features))
+            .unwrap_or(RenderMode::Shaded)
+    }
+
+    /// `base` adjusted for this mode.
+    pub fn description(&self, base: &PipelineDescription) -> PipelineDescription {
+        let mut description = base.clone();
+        description.polygon_mode = self.polygon_mode();
+        description
+            .constants
+            .set(DEBUG_VIEW_CONSTANT_ID, SpecializationValue::Int(self.debug_view()));
+
+        if *self == RenderMode::Overdraw {
+            description = description.with_blend(BlendPreset::Additive);
+            description.depth = description.depth.map(|depth| DepthConfig {
+                compare_op: vk::CompareOp::ALWAYS,
+                write: false,
+                ..depth
+            });
+        }
+        description
+    }
+}
+
+/// One pipeline per render mode, sharing a layout and created on first use so switching modes does not
+/// need a restart. The modes are variants of the base description.
+pub struct RenderModePipelines {
+    variants: PipelineVariants,
+}
+
+impl RenderModePipelines {
+    pub unsafe fn new(
+        device: &ash::Device,
+        base: PipelineDescription,
+        swapchain_extent: vk::Extent2D,
+        target: PipelineTarget,
+    ) -> Result<Self> {
+        Ok(Self {
+            variants: PipelineVariants::new(device, base, swapchain_extent, target)?,
+        })
+    }
+
+    pub fn base(&self) -> &PipelineDescription {
+        &self.variants.description
+    }
+
+    pub fn layout(&self) -> vk::PipelineLayout {
+        self.variants.layout
+    }
+
+    pub unsafe fn get(&mut self, device: &ash::Device, mode: RenderMode) -> Result<vk::Pipeline> {
+        let description = mode.description(&self.variants.description);
+        self.variants.variant(device, &description)
+    }
+
+    /// Any other variant of the base, e.g. a mode's description with blending turned on.
+    pub unsafe fn variant(&mut self, device: &ash::Device, description: &PipelineDescription) -> Result<vk::Pipeline> {
+        self.variants.variant(device, description)
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        self.variants.destroy(device);
+    }
+}
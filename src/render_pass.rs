@@ -0,0 +1,422 @@
+use std::ptr;
+
+use anyhow::{Error, Result};
+use ash::vk;
+
+/// One attachment of a render pass, the clear value is used when `load_op` is CLEAR.
+#[derive(Clone, Copy)]
+pub struct AttachmentInfo {
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub stencil_load_op: vk::AttachmentLoadOp,
+    pub stencil_store_op: vk::AttachmentStoreOp,
+    pub initial_layout: vk::ImageLayout,
+    pub final_layout: vk::ImageLayout,
+    pub clear_value: vk::ClearValue,
+}
+
+impl AttachmentInfo {
+    /// Color target that is cleared to black and kept.
+    pub fn color(format: vk::Format) -> Self {
+        Self {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            clear_value: vk::ClearValue {
+                // draw the frame black before drawing the scene
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+        }
+    }
+
+    /// Swapchain image, ends up ready to be presented.
+    pub fn present(format: vk::Format) -> Self {
+        Self {
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            ..Self::color(format)
+        }
+    }
+
+    /// Depth/stencil target cleared to the far plane, its content is thrown away after the pass.
+    pub fn depth(format: vk::Format) -> Self {
+        Self {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            stencil_load_op: vk::AttachmentLoadOp::CLEAR,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+            },
+        }
+    }
+
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_ops(mut self, load_op: vk::AttachmentLoadOp, store_op: vk::AttachmentStoreOp) -> Self {
+        self.load_op = load_op;
+        self.store_op = store_op;
+        self
+    }
+
+    pub fn with_stencil_ops(mut self, load_op: vk::AttachmentLoadOp, store_op: vk::AttachmentStoreOp) -> Self {
+        self.stencil_load_op = load_op;
+        self.stencil_store_op = store_op;
+        self
+    }
+
+    pub fn with_layouts(mut self, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> Self {
+        self.initial_layout = initial_layout;
+        self.final_layout = final_layout;
+        self
+    }
+
+    pub fn with_clear(mut self, clear_value: vk::ClearValue) -> Self {
+        self.clear_value = clear_value;
+        self
+    }
+
+    pub fn is_depth(&self) -> bool {
+        is_depth_format(self.format)
+    }
+
+    fn description(&self) -> vk::AttachmentDescription {
+        vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format: self.format,
+            samples: self.samples,
+            load_op: self.load_op,
+            store_op: self.store_op,
+            stencil_load_op: self.stencil_load_op,
+            stencil_store_op: self.stencil_store_op,
+            initial_layout: self.initial_layout,
+            final_layout: self.final_layout,
+        }
+    }
+}
+
+pub fn is_depth_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+/// Attachments a subpass uses, by index into `RenderPassDescription::attachments`.
+#[derive(Clone, Debug, Default)]
+pub struct SubpassInfo {
+    pub colors: Vec<u32>,
+    pub depth_stencil: Option<u32>,
+    /// either empty or one per color attachment
+    pub resolves: Vec<u32>,
+    pub inputs: Vec<u32>,
+    pub preserves: Vec<u32>,
+}
+
+impl SubpassInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn color(mut self, attachment: u32) -> Self {
+        self.colors.push(attachment);
+        self
+    }
+
+    pub fn depth_stencil(mut self, attachment: u32) -> Self {
+        self.depth_stencil = Some(attachment);
+        self
+    }
+
+    pub fn resolve(mut self, attachment: u32) -> Self {
+        self.resolves.push(attachment);
+        self
+    }
+
+    pub fn input(mut self, attachment: u32) -> Self {
+        self.inputs.push(attachment);
+        self
+    }
+
+    pub fn preserve(mut self, attachment: u32) -> Self {
+        self.preserves.push(attachment);
+        self
+    }
+}
+
+/// What goes into a framebuffer slot, the swapchain image changes per framebuffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramebufferAttachment {
+    Swapchain,
+    View(vk::ImageView),
+}
+
+#[derive(Clone, Default)]
+pub struct RenderPassDescription {
+    pub attachments: Vec<AttachmentInfo>,
+    pub subpasses: Vec<SubpassInfo>,
+    /// generated from the subpasses when left empty
+    pub dependencies: Vec<vk::SubpassDependency>,
+}
+
+impl RenderPassDescription {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A single subpass drawing straight into the swapchain image.
+    pub fn present(swapchain_format: vk::Format) -> Self {
+        Self::new()
+            .attachment(AttachmentInfo::present(swapchain_format))
+            .subpass(SubpassInfo::new().color(0))
+    }
+
+    /// Color and depth drawn into the swapchain image, with a multisampled color attachment resolved into it
+    /// when `samples` is above one. Attachments are color, depth, then the swapchain image if multisampled.
+    pub fn forward(swapchain_format: vk::Format, depth: AttachmentInfo, samples: vk::SampleCountFlags) -> Self {
+        if samples == vk::SampleCountFlags::TYPE_1 {
+            return Self::present(swapchain_format).with_depth(depth);
+        }
+
+        let color = AttachmentInfo::color(swapchain_format)
+            .with_samples(samples)
+            .with_ops(vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::DONT_CARE);
+        let resolve = AttachmentInfo::present(swapchain_format)
+            .with_ops(vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::STORE);
+
+        Self::new()
+            .attachment(color)
+            .attachment(depth.with_samples(samples))
+            .attachment(resolve)
+            .subpass(SubpassInfo::new().color(0).depth_stencil(1).resolve(2))
+    }
+
+    /// Adds a depth/stencil attachment and uses it in every subpass that has none yet.
+    pub fn with_depth(mut self, attachment: AttachmentInfo) -> Self {
+        let index = self.attachments.len() as u32;
+        self.attachments.push(attachment);
+        for subpass in self.subpasses.iter_mut() {
+            if subpass.depth_stencil.is_none() {
+                subpass.depth_stencil = Some(index);
+            }
+        }
+        self
+    }
+
+    pub fn attachment(mut self, attachment: AttachmentInfo) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn subpass(mut self, subpass: SubpassInfo) -> Self {
+        self.subpasses.push(subpass);
+        self
+    }
+
+    pub fn dependency(mut self, dependency: vk::SubpassDependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    /// One clear value per attachment, in the order `cmd_begin_render_pass` expects.
+    pub fn clear_values(&self) -> Vec<vk::ClearValue> {
+        self.attachments.iter().map(|attachment| attachment.clear_value).collect()
+    }
+
+    fn default_dependencies(&self) -> Vec<vk::SubpassDependency> {
+        let uses_depth = self.subpasses.iter().any(|subpass| subpass.depth_stencil.is_some());
+        let (stages, access) = if uses_depth {
+            (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+        } else {
+            (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )
+        };
+
+        // wait for the previous frame to be done with the attachments before writing to them
+        let mut dependencies = vec![vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            dst_subpass: 0,
+            src_stage_mask: stages,
+            dst_stage_mask: stages,
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: access,
+            dependency_flags: vk::DependencyFlags::empty(),
+        }];
+
+        // subpasses reading the previous one's output through input attachments
+        for (index, subpass) in self.subpasses.iter().enumerate().skip(1) {
+            if subpass.inputs.is_empty() {
+                continue;
+            }
+            dependencies.push(vk::SubpassDependency {
+                src_subpass: index as u32 - 1,
+                dst_subpass: index as u32,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::INPUT_ATTACHMENT_READ,
+                dependency_flags: vk::DependencyFlags::BY_REGION,
+            });
+        }
+        dependencies
+    }
+
+    fn validate(&self) -> Result<()> {
+        let count = self.attachments.len() as u32;
+        for (index, subpass) in self.subpasses.iter().enumerate() {
+            let referenced = subpass
+                .colors
+                .iter()
+                .chain(subpass.depth_stencil.iter())
+                .chain(subpass.resolves.iter())
+                .chain(subpass.inputs.iter())
+                .chain(subpass.preserves.iter());
+            for attachment in referenced {
+                if *attachment >= count {
+                    return Err(Error::msg(format!(
+                        "subpass {} references attachment {} but there are only {}",
+                        index, attachment, count
+                    )));
+                }
+            }
+            if !subpass.resolves.is_empty() && subpass.resolves.len() != subpass.colors.len() {
+                return Err(Error::msg(format!(
+                    "subpass {} needs one resolve attachment per color attachment",
+                    index
+                )));
+            }
+        }
+        if self.subpasses.is_empty() {
+            return Err(Error::msg("render pass needs at least one subpass"));
+        }
+        Ok(())
+    }
+}
+
+pub unsafe fn create_render_pass(device: &ash::Device, description: &RenderPassDescription) -> Result<vk::RenderPass> {
+    description.validate()?;
+
+    let color_ref = |attachment: &u32| vk::AttachmentReference {
+        attachment: *attachment,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    };
+    let input_ref = |attachment: &u32| vk::AttachmentReference {
+        attachment: *attachment,
+        layout: if description.attachments[*attachment as usize].is_depth() {
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+        } else {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        },
+    };
+
+    // the references have to stay alive until the render pass is created
+    let colors: Vec<Vec<vk::AttachmentReference>> = description
+        .subpasses
+        .iter()
+        .map(|subpass| subpass.colors.iter().map(color_ref).collect())
+        .collect();
+    let resolves: Vec<Vec<vk::AttachmentReference>> = description
+        .subpasses
+        .iter()
+        .map(|subpass| subpass.resolves.iter().map(color_ref).collect())
+        .collect();
+    let inputs: Vec<Vec<vk::AttachmentReference>> = description
+        .subpasses
+        .iter()
+        .map(|subpass| subpass.inputs.iter().map(input_ref).collect())
+        .collect();
+    let depth_stencils: Vec<Option<vk::AttachmentReference>> = description
+        .subpasses
+        .iter()
+        .map(|subpass| {
+            subpass.depth_stencil.map(|attachment| vk::AttachmentReference {
+                attachment,
+                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            })
+        })
+        .collect();
+
+    let subpasses: Vec<vk::SubpassDescription> = description
+        .subpasses
+        .iter()
+        .enumerate()
+        .map(|(index, subpass)| vk::SubpassDescription {
+            flags: vk::SubpassDescriptionFlags::empty(),
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            input_attachment_count: inputs[index].len() as u32,
+            p_input_attachments: inputs[index].as_ptr(),
+            color_attachment_count: colors[index].len() as u32,
+            p_color_attachments: colors[index].as_ptr(),
+            p_resolve_attachments: if resolves[index].is_empty() {
+                ptr::null()
+            } else {
+                resolves[index].as_ptr()
+            },
+            p_depth_stencil_attachment: match &depth_stencils[index] {
+                Some(depth) => depth,
+                None => ptr::null(),
+            },
+            preserve_attachment_count: subpass.preserves.len() as u32,
+            p_preserve_attachments: subpass.preserves.as_ptr(),
+        })
+        .collect();
+
+    let render_pass_attachments: Vec<vk::AttachmentDescription> = description
+        .attachments
+        .iter()
+        .map(|attachment| attachment.description())
+        .collect();
+
+    let subpass_dependencies = if description.dependencies.is_empty() {
+        description.default_dependencies()
+    } else {
+        description.dependencies.clone()
+    };
+
+    let renderpass_create_info = vk::RenderPassCreateInfo {
+        s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
+        flags: vk::RenderPassCreateFlags::empty(),
+        p_next: ptr::null(),
+        attachment_count: render_pass_attachments.len() as u32,
+        p_attachments: render_pass_attachments.as_ptr(),
+        subpass_count: subpasses.len() as u32,
+        p_subpasses: subpasses.as_ptr(),
+        dependency_count: subpass_dependencies.len() as u32,
+        p_dependencies: subpass_dependencies.as_ptr(),
+    };
+
+    Ok(device.create_render_pass(&renderpass_create_info, None)?)
+}
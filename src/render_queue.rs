@@ -0,0 +1,95 @@
+use std::cmp::Ordering;
+
+use ash::vk;
+
+use crate::{bindless::TextureHandle, blend::BlendPreset, push_constant::cmd_push_constants};
+
+/// One indexed draw.
+#[derive(Clone, Copy, Debug)]
+pub struct DrawItem {
+    pub pipeline: vk::Pipeline,
+    pub vertex_buffer: vk::Buffer,
+    pub index_buffer: vk::Buffer,
+    pub index_type: vk::IndexType,
+    pub index_count: u32,
+    /// pushed as the bindless texture index, the pipeline layout has to include `TextureHandle::range()`
+    pub texture: Option<TextureHandle>,
+    /// distance to the camera, orders the transparent draws
+    pub depth: f32,
+}
+
+/// Draws collected for a frame, opaque ones first and blended ones after them from back to front.
+#[derive(Default)]
+pub struct RenderQueue {
+    opaque: Vec<DrawItem>,
+    transparent: Vec<DrawItem>,
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `blend` is the preset the item's pipeline was built with.
+    pub fn push(&mut self, item: DrawItem, blend: BlendPreset) {
+        if blend.is_transparent() {
+            self.transparent.push(item);
+        } else {
+            self.opaque.push(item);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.transparent.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.opaque.is_empty() && self.transparent.is_empty()
+    }
+
+    /// Opaque draws are grouped by pipeline and go front to back so the depth test rejects early,
+    /// blended draws need the far ones first to composite correctly.
+    pub fn sort(&mut self) {
+        self.opaque.sort_by(|a, b| {
+            a.pipeline
+                .cmp(&b.pipeline)
+                .then_with(|| a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal))
+        });
+        self.transparent
+            .sort_by(|a, b| b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal));
+    }
+
+    /// Draw order after `sort`.
+    pub fn items(&self) -> impl Iterator<Item = &DrawItem> {
+        self.opaque.iter().chain(self.transparent.iter())
+    }
+
+    /// Records every draw, has to be inside a render pass or dynamic rendering with the viewport and scissor set.
+    /// `layout` is the one shared by the pipelines, texture indices are pushed through it.
+    pub unsafe fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, layout: vk::PipelineLayout) {
+        let mut bound_pipeline = vk::Pipeline::null();
+        let mut bound_buffers = None;
+        let mut pushed_texture = None;
+
+        for item in self.items() {
+            if item.pipeline != bound_pipeline {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, item.pipeline);
+                bound_pipeline = item.pipeline;
+            }
+            let buffers = (item.vertex_buffer, item.index_buffer, item.index_type);
+            if bound_buffers != Some(buffers) {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[item.vertex_buffer], &[0]);
+                device.cmd_bind_index_buffer(command_buffer, item.index_buffer, 0, item.index_type);
+                bound_buffers = Some(buffers);
+            }
+            if let Some(texture) = item.texture {
+                if pushed_texture != Some(texture) {
+                    cmd_push_constants(device, command_buffer, layout, &texture);
+                    pushed_texture = Some(texture);
+                }
+            }
+            device.cmd_draw_indexed(command_buffer, item.index_count, 1, 0, 0, 0);
+        }
+    }
+}
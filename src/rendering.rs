@@ -0,0 +1,272 @@
+use std::ptr;
+
+use ash::vk::{self, StructureType};
+
+use crate::device::DeviceFeatures;
+
+/// How frames are recorded, render pass objects or vulkan 1.3 dynamic rendering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    RenderPass,
+    Dynamic,
+}
+
+impl RenderPath {
+    /// Dynamic rendering when it is preferred and the device has it, the render pass path otherwise.
+    pub fn choose(prefer_dynamic: bool, features: &DeviceFeatures) -> Self {
+        if prefer_dynamic && features.dynamic_rendering {
+            RenderPath::Dynamic
+        } else {
+            RenderPath::RenderPass
+        }
+    }
+}
+
+/// Image drawn into with dynamic rendering.
+#[derive(Clone, Copy)]
+pub struct DynamicAttachment {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub aspect_mask: vk::ImageAspectFlags,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+    /// single sampled image the multisampled content is averaged into
+    pub resolve: Option<(vk::Image, vk::ImageView)>,
+}
+
+impl DynamicAttachment {
+    /// Color attachment cleared to black.
+    pub fn color(image: vk::Image, view: vk::ImageView) -> Self {
+        Self {
+            image,
+            view,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+            resolve: None,
+        }
+    }
+
+    /// Resolves into `image` at the end of rendering, the multisampled content itself is not kept.
+    pub fn with_resolve(mut self, image: vk::Image, view: vk::ImageView) -> Self {
+        self.resolve = Some((image, view));
+        self.store_op = vk::AttachmentStoreOp::DONT_CARE;
+        self
+    }
+
+    /// The image that holds the result once rendering ends.
+    pub fn output_image(&self) -> vk::Image {
+        self.resolve.map_or(self.image, |(image, _)| image)
+    }
+
+    /// Depth (and stencil) attachment, cleared and thrown away after the frame.
+    pub fn depth(
+        image: vk::Image,
+        view: vk::ImageView,
+        aspect_mask: vk::ImageAspectFlags,
+        clear_value: vk::ClearValue,
+    ) -> Self {
+        Self {
+            image,
+            view,
+            aspect_mask,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value,
+            resolve: None,
+        }
+    }
+
+    fn info(&self, layout: vk::ImageLayout) -> vk::RenderingAttachmentInfo {
+        let (resolve_mode, resolve_image_view, resolve_image_layout) = match self.resolve {
+            Some((_, view)) => (vk::ResolveModeFlags::AVERAGE, view, layout),
+            None => (vk::ResolveModeFlags::NONE, vk::ImageView::null(), vk::ImageLayout::UNDEFINED),
+        };
+        vk::RenderingAttachmentInfo {
+            s_type: StructureType::RENDERING_ATTACHMENT_INFO,
+            p_next: ptr::null(),
+            image_view: self.view,
+            image_layout: layout,
+            resolve_mode,
+            resolve_image_view,
+            resolve_image_layout,
+            load_op: self.load_op,
+            store_op: self.store_op,
+            clear_value: self.clear_value,
+        }
+    }
+}
+
+/// Where a frame is recorded to.
+pub enum FrameTarget<'a> {
+    RenderPass {
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        clear_values: &'a [vk::ClearValue],
+    },
+    /// the color attachment, or its resolve image, is a swapchain image, it is handed back in PRESENT_SRC_KHR
+    Dynamic {
+        color: DynamicAttachment,
+        depth: Option<DynamicAttachment>,
+    },
+}
+
+pub unsafe fn begin_frame_target(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    target: &FrameTarget,
+    extent: vk::Extent2D,
+) {
+    let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+
+    match target {
+        FrameTarget::RenderPass {
+            render_pass,
+            framebuffer,
+            clear_values,
+        } => {
+            let render_pass_info = vk::RenderPassBeginInfo {
+                s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
+                p_next: ptr::null(),
+                render_pass: *render_pass,
+                framebuffer: *framebuffer,
+                render_area,
+                clear_value_count: clear_values.len() as u32,
+                p_clear_values: clear_values.as_ptr(),
+            };
+
+            device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        }
+        FrameTarget::Dynamic { color, depth } => {
+            // what the render pass did with its initial layout and external dependency
+            let color_images = [Some(color.image), color.resolve.map(|(image, _)| image)];
+            for image in color_images.into_iter().flatten() {
+                image_barrier(
+                    device,
+                    command_buffer,
+                    image,
+                    vk::ImageAspectFlags::COLOR,
+                    (vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                    (
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    ),
+                    (vk::AccessFlags::empty(), vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+                );
+            }
+
+            let depth_attachment = depth.map(|depth| {
+                image_barrier(
+                    device,
+                    command_buffer,
+                    depth.image,
+                    depth.aspect_mask,
+                    (vk::ImageLayout::UNDEFINED, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                    (
+                        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                    ),
+                    (
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    ),
+                );
+                depth.info(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            });
+            let has_stencil = depth.is_some_and(|depth| depth.aspect_mask.contains(vk::ImageAspectFlags::STENCIL));
+
+            let color_attachments = [color.info(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+            let rendering_info = vk::RenderingInfo {
+                s_type: StructureType::RENDERING_INFO,
+                p_next: ptr::null(),
+                flags: vk::RenderingFlags::empty(),
+                render_area,
+                layer_count: 1,
+                view_mask: 0,
+                color_attachment_count: color_attachments.len() as u32,
+                p_color_attachments: color_attachments.as_ptr(),
+                p_depth_attachment: match &depth_attachment {
+                    Some(depth) => depth,
+                    None => ptr::null(),
+                },
+                p_stencil_attachment: match &depth_attachment {
+                    Some(stencil) if has_stencil => stencil,
+                    _ => ptr::null(),
+                },
+            };
+
+            device.cmd_begin_rendering(command_buffer, &rendering_info);
+        }
+    }
+}
+
+pub unsafe fn end_frame_target(device: &ash::Device, command_buffer: vk::CommandBuffer, target: &FrameTarget) {
+    match target {
+        FrameTarget::RenderPass { .. } => device.cmd_end_render_pass(command_buffer),
+        FrameTarget::Dynamic { color, .. } => {
+            device.cmd_end_rendering(command_buffer);
+
+            image_barrier(
+                device,
+                command_buffer,
+                color.output_image(),
+                vk::ImageAspectFlags::COLOR,
+                (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR),
+                (
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                ),
+                (vk::AccessFlags::COLOR_ATTACHMENT_WRITE, vk::AccessFlags::empty()),
+            );
+        }
+    }
+}
+
+/// Layout transition of every mip level and layer of `image`, given as (src, dst) pairs.
+pub unsafe fn image_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    layouts: (vk::ImageLayout, vk::ImageLayout),
+    stages: (vk::PipelineStageFlags, vk::PipelineStageFlags),
+    access: (vk::AccessFlags, vk::AccessFlags),
+) {
+    let barrier = vk::ImageMemoryBarrier {
+        s_type: StructureType::IMAGE_MEMORY_BARRIER,
+        p_next: ptr::null(),
+        src_access_mask: access.0,
+        dst_access_mask: access.1,
+        old_layout: layouts.0,
+        new_layout: layouts.1,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        },
+    };
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        stages.0,
+        stages.1,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[barrier],
+    );
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    ptr,
+};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::device::DeviceFeatures;
+
+/// Filters, mipmap mode, address modes, border color, anisotropy, compare op and the lod floats as bits.
+type SamplerKey = (
+    [vk::Filter; 2],
+    vk::SamplerMipmapMode,
+    [vk::SamplerAddressMode; 3],
+    vk::BorderColor,
+    Option<u32>,
+    Option<vk::CompareOp>,
+    [u32; 3],
+);
+
+/// Everything that makes up a sampler. Identical descriptions share one sampler through `SamplerCache`.
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDescription {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    /// only used by the CLAMP_TO_BORDER address mode
+    pub border_color: vk::BorderColor,
+    /// None turns anisotropic filtering off, clamped to `maxSamplerAnisotropy`
+    pub max_anisotropy: Option<f32>,
+    /// depth comparison for shadow maps, sampled through a sampler2DShadow
+    pub compare_op: Option<vk::CompareOp>,
+    pub mip_lod_bias: f32,
+    pub min_lod: f32,
+    pub max_lod: f32,
+}
+
+impl Default for SamplerDescription {
+    /// Trilinear filtering with repeat addressing over every mip level.
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            max_anisotropy: None,
+            compare_op: None,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+        }
+    }
+}
+
+impl SamplerDescription {
+    pub fn linear() -> Self {
+        Self::default()
+    }
+
+    /// Nearest texel of the nearest mip, e.g. for pixel art.
+    pub fn nearest() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..Default::default()
+        }
+    }
+
+    /// Depth comparison sampler for shadow maps, everything outside the map counts as lit.
+    pub fn shadow() -> Self {
+        Self {
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            compare_op: Some(vk::CompareOp::LESS_OR_EQUAL),
+            max_lod: 0.0,
+            ..Default::default()
+        }
+        .with_address_mode(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+    }
+
+    /// Same address mode along every axis.
+    pub fn with_address_mode(mut self, mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode_u = mode;
+        self.address_mode_v = mode;
+        self.address_mode_w = mode;
+        self
+    }
+
+    pub fn with_anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.max_anisotropy = Some(max_anisotropy);
+        self
+    }
+
+    /// Falls back to `nearest` when the image format cannot be filtered linearly, see `supports_linear_filter`.
+    /// Anisotropic filtering goes too, it needs linear filtering as well.
+    pub fn with_linear_filter_support(self, supported: bool) -> Self {
+        if supported {
+            return self;
+        }
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            max_anisotropy: None,
+            ..self
+        }
+    }
+
+    pub fn with_lod(mut self, min_lod: f32, max_lod: f32, mip_lod_bias: f32) -> Self {
+        self.min_lod = min_lod;
+        self.max_lod = max_lod;
+        self.mip_lod_bias = mip_lod_bias;
+        self
+    }
+
+    fn create_info(&self) -> vk::SamplerCreateInfo {
+        vk::SamplerCreateInfo {
+            s_type: StructureType::SAMPLER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::SamplerCreateFlags::empty(),
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_mode: self.mipmap_mode,
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: self.address_mode_w,
+            mip_lod_bias: self.mip_lod_bias,
+            anisotropy_enable: self.max_anisotropy.is_some() as vk::Bool32,
+            max_anisotropy: self.max_anisotropy.unwrap_or(1.0),
+            compare_enable: self.compare_op.is_some() as vk::Bool32,
+            compare_op: self.compare_op.unwrap_or(vk::CompareOp::ALWAYS),
+            min_lod: self.min_lod,
+            max_lod: self.max_lod,
+            border_color: self.border_color,
+            unnormalized_coordinates: vk::FALSE,
+        }
+    }
+
+    /// Floats compared by their bits, so equality and hashing agree.
+    fn key(&self) -> SamplerKey {
+        (
+            [self.mag_filter, self.min_filter],
+            self.mipmap_mode,
+            [self.address_mode_u, self.address_mode_v, self.address_mode_w],
+            self.border_color,
+            self.max_anisotropy.map(f32::to_bits),
+            self.compare_op,
+            [self.mip_lod_bias.to_bits(), self.min_lod.to_bits(), self.max_lod.to_bits()],
+        )
+    }
+}
+
+impl PartialEq for SamplerDescription {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDescription {}
+
+impl Hash for SamplerDescription {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// True if optimal tiled images of `format` may be sampled with LINEAR filters.
+pub unsafe fn supports_linear_filter(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    instance
+        .get_physical_device_format_properties(physical_device, format)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+}
+
+/// Samplers keyed by their description, the same description always gives back the same sampler.
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDescription, vk::Sampler>,
+    /// None without the `sampler_anisotropy` feature
+    max_anisotropy: Option<f32>,
+}
+
+impl SamplerCache {
+    pub unsafe fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, features: &DeviceFeatures) -> Self {
+        let limits = instance.get_physical_device_properties(physical_device).limits;
+        Self {
+            samplers: HashMap::new(),
+            max_anisotropy: features.sampler_anisotropy.then_some(limits.max_sampler_anisotropy),
+        }
+    }
+
+    /// The sampler is owned by the cache, do not destroy it yourself. Anisotropy is clamped to what the
+    /// device supports and dropped without the feature, before looking the description up.
+    pub unsafe fn get(&mut self, device: &ash::Device, description: &SamplerDescription) -> VkResult<vk::Sampler> {
+        let description = self.supported(description);
+        if let Some(sampler) = self.samplers.get(&description) {
+            return Ok(*sampler);
+        }
+
+        let sampler = device.create_sampler(&description.create_info(), None)?;
+        self.samplers.insert(description, sampler);
+        Ok(sampler)
+    }
+
+    /// `description` with the anisotropy the device can do.
+    fn supported(&self, description: &SamplerDescription) -> SamplerDescription {
+        let mut description = *description;
+        description.max_anisotropy = match (description.max_anisotropy, self.max_anisotropy) {
+            (Some(requested), Some(max)) if requested > 1.0 => Some(requested.min(max)),
+            _ => None,
+        };
+        description
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    /// Nothing may use the samplers anymore.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for (_, sampler) in self.samplers.drain() {
+            device.destroy_sampler(sampler, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+
+    use super::*;
+
+    fn hash(description: &SamplerDescription) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        description.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn cache(max_anisotropy: Option<f32>) -> SamplerCache {
+        SamplerCache {
+            samplers: HashMap::new(),
+            max_anisotropy,
+        }
+    }
+
+    #[test]
+    fn equal_descriptions_hash_equal() {
+        let a = SamplerDescription::linear().with_anisotropy(8.0);
+        let b = SamplerDescription::default().with_anisotropy(8.0);
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+
+        assert_ne!(a, SamplerDescription::linear());
+        assert_ne!(a, a.with_anisotropy(4.0));
+        assert_ne!(a, a.with_lod(0.0, 4.0, 0.0));
+        assert_ne!(a, a.with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE));
+        assert_ne!(SamplerDescription::nearest(), SamplerDescription::linear());
+    }
+
+    #[test]
+    fn floats_compare_by_bits() {
+        // 0.0 and -0.0 are equal floats, but not the same sampler bits
+        let positive = SamplerDescription::linear().with_lod(0.0, 1.0, 0.0);
+        let negative = SamplerDescription::linear().with_lod(0.0, 1.0, -0.0);
+        assert_ne!(positive, negative);
+
+        // NaN would never equal itself as a float
+        let nan = SamplerDescription::linear().with_lod(0.0, 1.0, f32::NAN);
+        assert_eq!(nan, nan);
+        assert_eq!(hash(&nan), hash(&nan));
+    }
+
+    #[test]
+    fn anisotropy_clamped_to_the_device() {
+        let cache = cache(Some(16.0));
+        let clamped = cache.supported(&SamplerDescription::linear().with_anisotropy(64.0));
+        assert_eq!(clamped.max_anisotropy, Some(16.0));
+        let kept = cache.supported(&SamplerDescription::linear().with_anisotropy(4.0));
+        assert_eq!(kept.max_anisotropy, Some(4.0));
+        // 1.0 is the same as none at all, both share a sampler
+        let one = cache.supported(&SamplerDescription::linear().with_anisotropy(1.0));
+        assert_eq!(one, cache.supported(&SamplerDescription::linear()));
+        assert_eq!(one.max_anisotropy, None);
+    }
+
+    #[test]
+    fn anisotropy_dropped_without_the_feature() {
+        let cache = cache(None);
+        let description = cache.supported(&SamplerDescription::linear().with_anisotropy(8.0));
+        assert_eq!(description.max_anisotropy, None);
+        assert_eq!(description.create_info().anisotropy_enable, vk::FALSE);
+    }
+
+    #[test]
+    fn nearest_without_linear_filter_support() {
+        let description = SamplerDescription::linear()
+            .with_anisotropy(8.0)
+            .with_address_mode(vk::SamplerAddressMode::MIRRORED_REPEAT);
+        assert_eq!(description.with_linear_filter_support(true), description);
+
+        let fallback = description.with_linear_filter_support(false);
+        assert_eq!(fallback.mag_filter, vk::Filter::NEAREST);
+        assert_eq!(fallback.min_filter, vk::Filter::NEAREST);
+        assert_eq!(fallback.mipmap_mode, vk::SamplerMipmapMode::NEAREST);
+        assert_eq!(fallback.max_anisotropy, None);
+        // everything else stays
+        assert_eq!(fallback.address_mode_u, vk::SamplerAddressMode::MIRRORED_REPEAT);
+        assert_eq!(fallback.max_lod, vk::LOD_CLAMP_NONE);
+        assert_eq!(
+            SamplerDescription::shadow().with_linear_filter_support(false).compare_op,
+            Some(vk::CompareOp::LESS_OR_EQUAL)
+        );
+    }
+}
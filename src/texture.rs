@@ -0,0 +1,202 @@
+use std::path::Path;
+
+use anyhow::{Error, Result};
+use ash::vk::{self, MemoryPropertyFlags};
+use stb_image::image::{self, LoadResult};
+
+use crate::{
+    allocator::{Allocation, MemoryAllocator},
+    buffer::{create_image_view, image_create_info},
+    deletion::{Deletion, DeletionQueue},
+    mipmap::{generate_mipmaps_cpu, MipGeneration},
+    upload::UploadContext,
+};
+
+/// Tightly packed 8 bit rgba texels.
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl ImageData {
+    /// Decodes a png, jpg, ... file, every format is expanded to 4 channels.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match image::load_with_depth(path, 4, false) {
+            LoadResult::ImageU8(image) => Ok(Self {
+                width: image.width as u32,
+                height: image.height as u32,
+                pixels: image.data,
+            }),
+            LoadResult::ImageF32(_) => Err(Error::msg(format!("{}: hdr images are not supported", path.display()))),
+            LoadResult::Error(e) => Err(Error::msg(format!("{}: {}", path.display(), e))),
+        }
+    }
+}
+
+/// A sampled 2d image with a view of every mip level, filled through an `UploadContext`.
+///
+/// Ready to sample once the upload ticket completed, it stays in SHADER_READ_ONLY_OPTIMAL from then on.
+/// Samplers come from a `sampler::SamplerCache`. Dropping it hands the image and view to the deletion queue.
+pub struct Texture {
+    image: vk::Image,
+    allocation: Allocation,
+    view: vk::ImageView,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    mip_levels: u32,
+    deletion: DeletionQueue,
+}
+
+impl Texture {
+    /// Loads an srgb color texture from disk.
+    pub unsafe fn from_file(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        path: impl AsRef<Path>,
+        mipmaps: MipGeneration,
+    ) -> Result<Self> {
+        let data = ImageData::load(path)?;
+        Self::from_pixels(
+            device,
+            allocator,
+            deletion,
+            uploads,
+            &data,
+            vk::Format::R8G8B8A8_SRGB,
+            mipmaps,
+        )
+    }
+
+    /// `format` has to be a 4 byte per texel format matching `data`, e.g. R8G8B8A8_UNORM for normal maps.
+    /// Blit generation needs a format with linear blit support, see `MipGeneration::for_format`.
+    pub unsafe fn from_pixels(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        deletion: &DeletionQueue,
+        uploads: &mut UploadContext,
+        data: &ImageData,
+        format: vk::Format,
+        mipmaps: MipGeneration,
+    ) -> Result<Self> {
+        let size = (data.width as usize)
+            .checked_mul(data.height as usize)
+            .and_then(|texels| texels.checked_mul(4));
+        if size != Some(data.pixels.len()) {
+            return Err(Error::msg(format!(
+                "{} bytes are not tightly packed rgba of a {}x{} image",
+                data.pixels.len(),
+                data.width,
+                data.height
+            )));
+        }
+        let mip_levels = mipmaps.levels(data.width, data.height);
+        let mut usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        if mipmaps == MipGeneration::Blit {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+        let image_info = vk::ImageCreateInfo {
+            mip_levels,
+            ..image_create_info(
+                data.width,
+                data.height,
+                format,
+                vk::SampleCountFlags::TYPE_1,
+                vk::ImageTiling::OPTIMAL,
+                usage,
+            )
+        };
+        let (image, allocation) = allocator.create_image(device, &image_info, MemoryPropertyFlags::DEVICE_LOCAL)?;
+        // owned from here on, drop cleans up if anything below fails
+        let mut texture = Self {
+            image,
+            allocation,
+            view: vk::ImageView::null(),
+            extent: vk::Extent2D {
+                width: data.width,
+                height: data.height,
+            },
+            format,
+            mip_levels,
+            deletion: deletion.clone(),
+        };
+
+        let layout = texture.layout();
+        match mipmaps {
+            MipGeneration::None => uploads.upload_image(
+                device,
+                &data.pixels,
+                image,
+                image_info.extent,
+                vk::ImageAspectFlags::COLOR,
+                layout,
+            )?,
+            MipGeneration::Blit => {
+                uploads.upload_image_mipmapped(device, &data.pixels, image, image_info.extent, mip_levels, layout)?
+            }
+            MipGeneration::Cpu => {
+                let srgb = format == vk::Format::R8G8B8A8_SRGB || format == vk::Format::B8G8R8A8_SRGB;
+                let smaller = generate_mipmaps_cpu(data.width, data.height, &data.pixels, srgb);
+                let levels: Vec<&[u8]> = std::iter::once(data.pixels.as_slice())
+                    .chain(smaller.iter().map(Vec::as_slice))
+                    .collect();
+                uploads.upload_image_levels(
+                    device,
+                    &levels,
+                    image,
+                    image_info.extent,
+                    vk::ImageAspectFlags::COLOR,
+                    layout,
+                )?
+            }
+        }
+        texture.view = create_image_view(device, image, format, vk::ImageAspectFlags::COLOR, mip_levels)?;
+        Ok(texture)
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    /// Layout to put in descriptor writes, the upload leaves the image in it.
+    pub fn layout(&self) -> vk::ImageLayout {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    }
+
+    /// For COMBINED_IMAGE_SAMPLER descriptors.
+    pub fn descriptor_info(&self, sampler: vk::Sampler) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler,
+            image_view: self.view,
+            image_layout: self.layout(),
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        if self.view != vk::ImageView::null() {
+            self.deletion.push(Deletion::ImageView(self.view));
+        }
+        self.deletion.push(Deletion::Image(self.image, self.allocation));
+    }
+}
@@ -0,0 +1,137 @@
+use std::{marker::PhantomData, mem::size_of, ptr::NonNull};
+
+use ash::{prelude::VkResult, vk};
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::{
+    allocator::{Allocation, MemoryAllocator},
+    descriptor::{DescriptorAllocator, DescriptorBinding, DescriptorLayoutCache, DescriptorWriter},
+    memory::MemoryProperties,
+};
+
+/// `layout(set = 0, binding = 0) uniform Transforms` in shader.vert
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Transforms {
+    pub model: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+}
+
+impl Default for Transforms {
+    fn default() -> Self {
+        Self {
+            model: Matrix4::identity(),
+            view: Matrix4::identity(),
+            projection: Matrix4::identity(),
+        }
+    }
+}
+
+/// Right handed perspective projection for vulkan clip space, y points down and depth goes from 0 to 1,
+/// or from 1 to 0 with `reverse_z`.
+pub fn perspective(fovy: f32, aspect: f32, near: f32, far: f32, reverse_z: bool) -> Matrix4<f32> {
+    // swapping the planes is all reverse z takes
+    let (near_depth, far_depth) = if reverse_z { (far, near) } else { (near, far) };
+    let focal = 1.0 / (fovy / 2.0).tan();
+    let range = near_depth - far_depth;
+
+    #[rustfmt::skip]
+    let projection = Matrix4::new(
+        focal / aspect, 0.0, 0.0, 0.0,
+        0.0, -focal, 0.0, 0.0,
+        0.0, 0.0, far_depth / range, near_depth * far_depth / range,
+        0.0, 0.0, -1.0, 0.0,
+    );
+    projection
+}
+
+pub fn look_at(eye: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) -> Matrix4<f32> {
+    Matrix4::look_at_rh(&eye, &target, &up)
+}
+
+/// One persistently mapped uniform buffer and descriptor set per frame in flight.
+///
+/// A frame's buffer is only written after waiting on that frame's fence, so the gpu never reads it mid update.
+pub struct UniformBuffers<T: Copy> {
+    buffers: Vec<vk::Buffer>,
+    allocations: Vec<Allocation>,
+    mapped: Vec<NonNull<u8>>,
+    /// owned by the layout cache passed to `new`
+    pub set_layout: vk::DescriptorSetLayout,
+    pub sets: Vec<vk::DescriptorSet>,
+    marker: PhantomData<T>,
+}
+
+impl<T: Copy> UniformBuffers<T> {
+    /// `frames` is the number of frames in flight, the block is visible to `stages` at binding 0.
+    pub unsafe fn new(
+        device: &ash::Device,
+        allocator: &mut MemoryAllocator,
+        layouts: &mut DescriptorLayoutCache,
+        descriptors: &mut DescriptorAllocator,
+        frames: usize,
+        stages: vk::ShaderStageFlags,
+    ) -> VkResult<Self> {
+        let set_layout = layouts.get(
+            device,
+            &[DescriptorBinding::new(0, vk::DescriptorType::UNIFORM_BUFFER, stages)],
+        )?;
+
+        let mut uniforms = Self {
+            buffers: vec![],
+            allocations: vec![],
+            mapped: vec![],
+            set_layout,
+            sets: vec![],
+            marker: PhantomData,
+        };
+        for _ in 0..frames {
+            // host coherent, so writes need no flush, and the allocator keeps it mapped
+            let (buffer, allocation) = match allocator.create_buffer(
+                device,
+                size_of::<T>() as vk::DeviceSize,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                MemoryProperties::upload(),
+            ) {
+                Ok(created) => created,
+                Err(e) => {
+                    uniforms.destroy(device, allocator);
+                    return Err(e);
+                }
+            };
+            uniforms.buffers.push(buffer);
+            uniforms.allocations.push(allocation);
+            uniforms
+                .mapped
+                .push(allocation.mapped.expect("host visible memory is mapped"));
+
+            let set = match descriptors.allocate(device, layouts, set_layout) {
+                Ok(set) => set,
+                Err(e) => {
+                    uniforms.destroy(device, allocator);
+                    return Err(e);
+                }
+            };
+            DescriptorWriter::new()
+                .write_buffer(0, vk::DescriptorType::UNIFORM_BUFFER, buffer, 0, size_of::<T>() as u64)
+                .update(device, set);
+            uniforms.sets.push(set);
+        }
+        Ok(uniforms)
+    }
+
+    /// Writes the block of `frame`, call after waiting for that frame's fence.
+    pub unsafe fn update(&mut self, frame: usize, value: &T) {
+        (self.mapped[frame].as_ptr() as *mut T).write_unaligned(*value);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device, allocator: &mut MemoryAllocator) {
+        for (buffer, allocation) in self.buffers.drain(..).zip(self.allocations.drain(..)) {
+            device.destroy_buffer(buffer, None);
+            allocator.free(device, allocation);
+        }
+        self.mapped.clear();
+        self.sets.clear();
+    }
+}
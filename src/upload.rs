@@ -0,0 +1,899 @@
+use std::{collections::VecDeque, ffi::c_void, mem::size_of_val, ptr};
+
+use anyhow::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, MemoryPropertyFlags, StructureType},
+};
+
+use crate::{
+    buffer::create_buffer,
+    device::DeviceFeatures,
+    mipmap::{cmd_generate_mipmaps, mip_extent},
+    FamilyQueue,
+};
+
+/// Size of the staging ring the uploads are copied through.
+pub const DEFAULT_STAGING_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+
+/// Identifies one submitted batch of uploads. With timeline semaphores it is the value the upload
+/// timeline reaches once the batch finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UploadTicket(u64);
+
+impl UploadTicket {
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+struct Batch {
+    ticket: u64,
+    /// None when the batch signals the timeline semaphore instead
+    fence: Option<vk::Fence>,
+    command_buffer: vk::CommandBuffer,
+    /// graphics command buffer acquiring the resources and the semaphore it waits on
+    acquire: Option<(vk::CommandBuffer, vk::Semaphore)>,
+    /// ring position right after the batch's staging data
+    end: vk::DeviceSize,
+}
+
+/// State for handing resources from the transfer family over to the graphics family.
+struct OwnershipTransfer {
+    graphics_queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    free_command_buffers: Vec<vk::CommandBuffer>,
+    free_semaphores: Vec<vk::Semaphore>,
+    /// acquire halves of the release barriers recorded into the current batch
+    buffer_acquires: Vec<vk::BufferMemoryBarrier>,
+    image_acquires: Vec<vk::ImageMemoryBarrier>,
+    /// blitted on the graphics queue right after the acquire
+    mip_chains: Vec<MipChain>,
+}
+
+/// Mips 1.. of an image waiting to be blitted from mip 0.
+#[derive(Clone, Copy)]
+struct MipChain {
+    image: vk::Image,
+    extent: vk::Extent3D,
+    levels: u32,
+    final_layout: vk::ImageLayout,
+}
+
+impl MipChain {
+    unsafe fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        cmd_generate_mipmaps(
+            device,
+            command_buffer,
+            self.image,
+            self.extent,
+            self.levels,
+            self.final_layout,
+        );
+    }
+}
+
+/// Copies buffer and image data to the gpu through one persistently mapped staging ring.
+///
+/// Uploads are recorded into the current batch until `flush` submits it and returns a ticket, without
+/// blocking. The renderer keeps drawing and starts using the data once `is_complete` reports the ticket
+/// done, or makes its own submission wait on `timeline_wait`. Staging space of a batch is reused once
+/// the batch finished, only a full ring flushes and waits for the oldest batch on its own.
+///
+/// Completion is tracked with one timeline semaphore counting up the tickets when the device has
+/// timeline semaphores (vulkan 1.2), with a fence per batch otherwise.
+///
+/// Resources are exclusive to one queue family. When the transfer family differs from the graphics family
+/// every upload is released on the transfer queue and acquired on the graphics queue, which waits on a
+/// semaphore the transfer submission signals. With a single family a barrier at the end of the batch is enough.
+pub struct UploadContext {
+    queue: vk::Queue,
+    queue_family: u32,
+    graphics_family: u32,
+    ownership_transfer: Option<OwnershipTransfer>,
+    /// signaled with each batch's ticket
+    timeline: Option<vk::Semaphore>,
+    command_pool: vk::CommandPool,
+    staging: vk::Buffer,
+    staging_memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    capacity: vk::DeviceSize,
+    /// staging offsets stay aligned to this for image copies
+    alignment: vk::DeviceSize,
+    /// ring positions only grow, the offset into the buffer is the position modulo `capacity`
+    head: vk::DeviceSize,
+    tail: vk::DeviceSize,
+    recording: Option<vk::CommandBuffer>,
+    in_flight: VecDeque<Batch>,
+    free_fences: Vec<vk::Fence>,
+    free_command_buffers: Vec<vk::CommandBuffer>,
+    next_ticket: u64,
+    /// every ticket up to this one has completed
+    completed: u64,
+}
+
+impl UploadContext {
+    /// Copies run on the `transfer` queue, the uploaded resources end up owned by the `graphics` family.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        transfer: FamilyQueue,
+        graphics: FamilyQueue,
+        features: &DeviceFeatures,
+        staging_size: vk::DeviceSize,
+    ) -> VkResult<Self> {
+        let timeline = if features.timeline_semaphore {
+            let type_info = vk::SemaphoreTypeCreateInfo {
+                s_type: StructureType::SEMAPHORE_TYPE_CREATE_INFO,
+                p_next: ptr::null(),
+                semaphore_type: vk::SemaphoreType::TIMELINE,
+                initial_value: 0,
+            };
+            let semaphore_info = vk::SemaphoreCreateInfo {
+                s_type: StructureType::SEMAPHORE_CREATE_INFO,
+                p_next: &type_info as *const vk::SemaphoreTypeCreateInfo as *const c_void,
+                flags: vk::SemaphoreCreateFlags::empty(),
+            };
+            Some(device.create_semaphore(&semaphore_info, None)?)
+        } else {
+            None
+        };
+
+        let command_pool = create_transient_pool(device, transfer.family)?;
+        let ownership_transfer = if transfer.family != graphics.family {
+            Some(OwnershipTransfer {
+                graphics_queue: graphics.queue,
+                command_pool: create_transient_pool(device, graphics.family)?,
+                free_command_buffers: vec![],
+                free_semaphores: vec![],
+                buffer_acquires: vec![],
+                image_acquires: vec![],
+                mip_chains: vec![],
+            })
+        } else {
+            None
+        };
+
+        // memory of its own, host coherent so the ring never needs a flush
+        let (staging, staging_memory) = create_buffer(
+            device,
+            instance,
+            physical_device,
+            staging_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mapped = device.map_memory(staging_memory, 0, staging_size, vk::MemoryMapFlags::empty())? as *mut u8;
+
+        // 16 covers every power of two texel size
+        let limits = instance.get_physical_device_properties(physical_device).limits;
+        let alignment = limits.optimal_buffer_copy_offset_alignment.max(16);
+
+        Ok(Self {
+            queue: transfer.queue,
+            queue_family: transfer.family,
+            graphics_family: graphics.family,
+            ownership_transfer,
+            timeline,
+            command_pool,
+            staging,
+            staging_memory,
+            mapped,
+            capacity: staging_size,
+            alignment,
+            head: 0,
+            tail: 0,
+            recording: None,
+            in_flight: VecDeque::new(),
+            free_fences: vec![],
+            free_command_buffers: vec![],
+            next_ticket: 1,
+            completed: 0,
+        })
+    }
+
+    /// Queues a copy of `data` into `dst` at `dst_offset`, split into pieces when it is larger than the ring.
+    pub unsafe fn upload_buffer<T: Copy>(
+        &mut self,
+        device: &ash::Device,
+        data: &[T],
+        dst: vk::Buffer,
+        dst_offset: vk::DeviceSize,
+    ) -> VkResult<()> {
+        let bytes = std::slice::from_raw_parts(data.as_ptr() as *const u8, size_of_val(data));
+        let chunk_size = (self.capacity / 2) as usize;
+        let mut written = 0;
+        for chunk in bytes.chunks(chunk_size) {
+            let src_offset = self.stage(device, chunk)?;
+            let region = vk::BufferCopy {
+                src_offset,
+                dst_offset: dst_offset + written,
+                size: chunk.len() as vk::DeviceSize,
+            };
+            let command_buffer = self.command_buffer(device)?;
+            device.cmd_copy_buffer(command_buffer, self.staging, dst, &[region]);
+            self.release_buffer(device, command_buffer, dst, region.dst_offset, region.size);
+            written += chunk.len() as vk::DeviceSize;
+        }
+        Ok(())
+    }
+
+    /// Queues a copy of tightly packed texels into mip 0, layer 0 of `image` and leaves it in `final_layout`.
+    /// The previous contents are discarded. Levels larger than the ring are copied a few rows at a time.
+    pub unsafe fn upload_image(
+        &mut self,
+        device: &ash::Device,
+        data: &[u8],
+        image: vk::Image,
+        extent: vk::Extent3D,
+        aspect_mask: vk::ImageAspectFlags,
+        final_layout: vk::ImageLayout,
+    ) -> Result<()> {
+        self.upload_image_levels(device, &[data], image, extent, aspect_mask, final_layout)
+    }
+
+    /// Like `upload_image` for a whole mip chain, `levels[i]` fills mip i. `extent` is the extent of mip 0.
+    /// Every level has to use the same texel size, nothing is recorded without levels.
+    pub unsafe fn upload_image_levels(
+        &mut self,
+        device: &ash::Device,
+        levels: &[&[u8]],
+        image: vk::Image,
+        extent: vk::Extent3D,
+        aspect_mask: vk::ImageAspectFlags,
+        final_layout: vk::ImageLayout,
+    ) -> Result<()> {
+        let Some(first) = levels.first() else {
+            return Ok(());
+        };
+        let expected = texel_size(first, extent)?;
+        let mut command_buffer = vk::CommandBuffer::null();
+        for (level, data) in levels.iter().enumerate() {
+            let level = level as u32;
+            let level_extent = mip_extent(extent, level);
+            if texel_size(data, level_extent)? != expected {
+                return Err(Error::msg(format!("mip {} has a different texel size than mip 0", level)));
+            }
+            let range = vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: level,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            };
+            command_buffer = self.copy_level(device, data, image, level_extent, range)?;
+        }
+        let range = vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: levels.len() as u32,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        self.finish_image(
+            device,
+            command_buffer,
+            image,
+            range,
+            final_layout,
+            vk::AccessFlags::MEMORY_READ,
+        );
+        Ok(())
+    }
+
+    /// Copies `data` into mip 0 of a color image and blits it down into the other `mip_levels - 1` levels.
+    ///
+    /// Blits need a graphics queue, so with separate families they are recorded after the acquire on the
+    /// graphics queue. The format has to support linear blits, see `MipGeneration::for_format`.
+    pub unsafe fn upload_image_mipmapped(
+        &mut self,
+        device: &ash::Device,
+        data: &[u8],
+        image: vk::Image,
+        extent: vk::Extent3D,
+        mip_levels: u32,
+        final_layout: vk::ImageLayout,
+    ) -> Result<()> {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let command_buffer = self.copy_level(device, data, image, extent, range)?;
+        let chain = MipChain {
+            image,
+            extent,
+            levels: mip_levels,
+            final_layout,
+        };
+        match self.ownership_transfer.as_mut() {
+            // the upload queue is the graphics family
+            None => chain.record(device, command_buffer),
+            Some(transfer) => {
+                transfer.mip_chains.push(chain);
+                let range = vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: mip_levels,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                };
+                // handed over as is, the blits continue in TRANSFER_DST_OPTIMAL
+                self.finish_image(
+                    device,
+                    command_buffer,
+                    image,
+                    range,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Stages `data` and records its copy into layer 0 of mip `range.base_mip_level`, after moving all of
+    /// `range` from UNDEFINED to TRANSFER_DST_OPTIMAL. Returns the command buffer the last copy went into.
+    ///
+    /// Split into ranges of whole rows of one depth slice when it is larger than the ring, the way
+    /// `upload_buffer` splits into chunks. A single row still has to fit.
+    unsafe fn copy_level(
+        &mut self,
+        device: &ash::Device,
+        data: &[u8],
+        image: vk::Image,
+        extent: vk::Extent3D,
+        range: vk::ImageSubresourceRange,
+    ) -> Result<vk::CommandBuffer> {
+        let row_size = extent.width as usize * texel_size(data, extent)?;
+        let rows_per_chunk = ((self.capacity / 2) as usize / row_size).max(1) as u32;
+
+        let mut command_buffer = vk::CommandBuffer::null();
+        let chunks = (0..extent.depth).flat_map(|slice| {
+            (0..extent.height)
+                .step_by(rows_per_chunk as usize)
+                .map(move |row| (slice, row))
+        });
+        for (slice, row) in chunks {
+            let height = rows_per_chunk.min(extent.height - row);
+            let start = (slice * extent.height + row) as usize * row_size;
+            let src_offset = self.stage(device, &data[start..start + height as usize * row_size])?;
+            // staging may have flushed the batch the barrier went into, later copies go into a new one,
+            // still ordered after it on the queue
+            let first = command_buffer == vk::CommandBuffer::null();
+            command_buffer = self.command_buffer(device)?;
+            if first {
+                self.to_transfer_dst(device, command_buffer, image, range);
+            }
+
+            let region = vk::BufferImageCopy {
+                buffer_offset: src_offset,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: range.aspect_mask,
+                    mip_level: range.base_mip_level,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D {
+                    x: 0,
+                    y: row as i32,
+                    z: slice as i32,
+                },
+                image_extent: vk::Extent3D {
+                    width: extent.width,
+                    height,
+                    depth: 1,
+                },
+            };
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                self.staging,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+        }
+        Ok(command_buffer)
+    }
+
+    /// Discards the contents of `range` and readies it for copies.
+    unsafe fn to_transfer_dst(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+    ) {
+        let to_transfer = vk::ImageMemoryBarrier {
+            s_type: StructureType::IMAGE_MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: range,
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer],
+        );
+    }
+
+    /// Moves the copied levels to `final_layout`, and over to the graphics family when it differs.
+    /// `acquire_access` is what the graphics family does with the image first.
+    unsafe fn finish_image(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        final_layout: vk::ImageLayout,
+        acquire_access: vk::AccessFlags,
+    ) {
+        // visibility comes from the barrier at the end of the batch or from the acquire, this only
+        // finishes the transfer and changes the layout
+        let mut to_final = vk::ImageMemoryBarrier {
+            s_type: StructureType::IMAGE_MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::empty(),
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: final_layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: range,
+        };
+        if let Some(transfer) = self.ownership_transfer.as_mut() {
+            // release and acquire both carry the same layout change
+            to_final.src_queue_family_index = self.queue_family;
+            to_final.dst_queue_family_index = self.graphics_family;
+            transfer.image_acquires.push(vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_access_mask: acquire_access,
+                ..to_final
+            });
+        }
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_final],
+        );
+    }
+
+    /// Hands a copied buffer range to the graphics family, nothing to do within one family.
+    unsafe fn release_buffer(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) {
+        let Some(transfer) = self.ownership_transfer.as_mut() else {
+            return;
+        };
+        let release = vk::BufferMemoryBarrier {
+            s_type: StructureType::BUFFER_MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::empty(),
+            src_queue_family_index: self.queue_family,
+            dst_queue_family_index: self.graphics_family,
+            buffer,
+            offset,
+            size,
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[release],
+            &[],
+        );
+        transfer.buffer_acquires.push(vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::MEMORY_READ,
+            ..release
+        });
+    }
+
+    /// Submits everything recorded since the last flush. Without pending uploads the ticket of the
+    /// last submitted batch is returned.
+    ///
+    /// The acquire submission reports completion when there is one, so a completed ticket means the
+    /// graphics family owns the resources.
+    pub unsafe fn flush(&mut self, device: &ash::Device) -> VkResult<UploadTicket> {
+        let Some(command_buffer) = self.recording.take() else {
+            return Ok(UploadTicket(self.next_ticket - 1));
+        };
+        if self.ownership_transfer.is_none() {
+            // same family, later submissions on any queue of it see the copies
+            let barrier = vk::MemoryBarrier {
+                s_type: StructureType::MEMORY_BARRIER,
+                p_next: ptr::null(),
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::MEMORY_READ,
+            };
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+        }
+        device.end_command_buffer(command_buffer)?;
+
+        let ticket = self.next_ticket;
+        let completion = match self.timeline {
+            Some(timeline) => Completion::Timeline(timeline, ticket),
+            None => Completion::Fence(match self.free_fences.pop() {
+                Some(fence) => fence,
+                None => {
+                    let fence_info = vk::FenceCreateInfo {
+                        s_type: StructureType::FENCE_CREATE_INFO,
+                        p_next: ptr::null(),
+                        flags: vk::FenceCreateFlags::empty(),
+                    };
+                    device.create_fence(&fence_info, None)?
+                }
+            }),
+        };
+        let acquire = match self.ownership_transfer.as_mut() {
+            Some(transfer) => Some(transfer.submit(device, self.queue, command_buffer, completion)?),
+            None => {
+                submit(device, self.queue, command_buffer, None, None, Some(completion))?;
+                None
+            }
+        };
+
+        self.next_ticket += 1;
+        self.in_flight.push_back(Batch {
+            ticket,
+            fence: match completion {
+                Completion::Fence(fence) => Some(fence),
+                Completion::Timeline(..) => None,
+            },
+            command_buffer,
+            acquire,
+            end: self.head,
+        });
+        Ok(UploadTicket(ticket))
+    }
+
+    /// Checks for finished batches without blocking and reclaims their staging space.
+    pub unsafe fn poll(&mut self, device: &ash::Device) -> VkResult<()> {
+        if let Some(timeline) = self.timeline {
+            let value = device.get_semaphore_counter_value(timeline)?;
+            while self.in_flight.front().is_some_and(|batch| batch.ticket <= value) {
+                self.retire(device)?;
+            }
+            return Ok(());
+        }
+        while let Some(batch) = self.in_flight.front() {
+            if !device.get_fence_status(batch.fence.expect("fence of a batch"))? {
+                break;
+            }
+            self.retire(device)?;
+        }
+        Ok(())
+    }
+
+    /// True once the gpu finished the batch of `ticket`, never blocks.
+    pub unsafe fn is_complete(&mut self, device: &ash::Device, ticket: UploadTicket) -> VkResult<bool> {
+        self.poll(device)?;
+        Ok(ticket.0 <= self.completed)
+    }
+
+    /// Semaphore and value a queue submission can wait on to start after the batch of `ticket`, instead of
+    /// waiting on the cpu. Needs timeline semaphores, and the ticket has to be flushed already.
+    pub fn timeline_wait(&self, ticket: UploadTicket) -> Option<(vk::Semaphore, u64)> {
+        debug_assert!(ticket.0 < self.next_ticket, "ticket of a batch that was not flushed");
+        self.timeline.map(|timeline| (timeline, ticket.0))
+    }
+
+    /// Blocks until the batch of `ticket` and every batch before it finished.
+    pub unsafe fn wait(&mut self, device: &ash::Device, ticket: UploadTicket) -> VkResult<()> {
+        if ticket.0 <= self.completed || self.in_flight.is_empty() {
+            return Ok(());
+        }
+        if let Some(timeline) = self.timeline {
+            let wait_info = vk::SemaphoreWaitInfo {
+                s_type: StructureType::SEMAPHORE_WAIT_INFO,
+                p_next: ptr::null(),
+                flags: vk::SemaphoreWaitFlags::empty(),
+                semaphore_count: 1,
+                p_semaphores: &timeline,
+                p_values: &ticket.0,
+            };
+            device.wait_semaphores(&wait_info, u64::MAX)?;
+            return self.poll(device);
+        }
+        while ticket.0 > self.completed {
+            let Some(batch) = self.in_flight.front() else {
+                break;
+            };
+            device.wait_for_fences(&[batch.fence.expect("fence of a batch")], true, u64::MAX)?;
+            self.retire(device)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes and waits for every upload.
+    pub unsafe fn wait_idle(&mut self, device: &ash::Device) -> VkResult<()> {
+        let ticket = self.flush(device)?;
+        self.wait(device, ticket)
+    }
+
+    /// Waits for pending uploads first.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        // nothing useful can be done about a lost device here
+        let _ = self.wait_idle(device);
+        for fence in self.free_fences.drain(..) {
+            device.destroy_fence(fence, None);
+        }
+        if let Some(timeline) = self.timeline {
+            device.destroy_semaphore(timeline, None);
+        }
+        if let Some(transfer) = self.ownership_transfer.as_mut() {
+            for semaphore in transfer.free_semaphores.drain(..) {
+                device.destroy_semaphore(semaphore, None);
+            }
+            device.destroy_command_pool(transfer.command_pool, None);
+        }
+        device.destroy_command_pool(self.command_pool, None);
+        device.unmap_memory(self.staging_memory);
+        device.destroy_buffer(self.staging, None);
+        device.free_memory(self.staging_memory, None);
+    }
+
+    /// The oldest batch finished, give its staging space, fence and command buffers back.
+    unsafe fn retire(&mut self, device: &ash::Device) -> VkResult<()> {
+        let batch = self.in_flight.pop_front().expect("a batch in flight");
+        if let Some(fence) = batch.fence {
+            device.reset_fences(&[fence])?;
+            self.free_fences.push(fence);
+        }
+        self.free_command_buffers.push(batch.command_buffer);
+        if let (Some((command_buffer, semaphore)), Some(transfer)) = (batch.acquire, self.ownership_transfer.as_mut()) {
+            transfer.free_command_buffers.push(command_buffer);
+            transfer.free_semaphores.push(semaphore);
+        }
+        self.tail = batch.end;
+        self.completed = batch.ticket;
+        Ok(())
+    }
+
+    /// The batch currently being recorded, started on first use.
+    unsafe fn command_buffer(&mut self, device: &ash::Device) -> VkResult<vk::CommandBuffer> {
+        if let Some(command_buffer) = self.recording {
+            return Ok(command_buffer);
+        }
+        let command_buffer = begin_pooled(device, self.command_pool, &mut self.free_command_buffers)?;
+        self.recording = Some(command_buffer);
+        Ok(command_buffer)
+    }
+
+    /// Copies `data` into the ring and returns its offset in the staging buffer.
+    unsafe fn stage(&mut self, device: &ash::Device, data: &[u8]) -> VkResult<vk::DeviceSize> {
+        let size = data.len() as vk::DeviceSize;
+        if size > self.capacity {
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+        self.poll(device)?;
+
+        let start = loop {
+            let mut start = self.head.next_multiple_of(self.alignment);
+            // data never wraps around the end of the buffer
+            if start % self.capacity + size > self.capacity {
+                start = start.next_multiple_of(self.capacity);
+            }
+            if start + size - self.tail <= self.capacity {
+                break start;
+            }
+            if self.in_flight.is_empty() && self.recording.is_none() {
+                // nothing uses the ring, start over at its beginning
+                self.head = self.head.next_multiple_of(self.capacity);
+                self.tail = self.head;
+                continue;
+            }
+            // the ring is full, make room by finishing the oldest batch
+            if self.in_flight.is_empty() {
+                self.flush(device)?;
+            }
+            let oldest = UploadTicket(self.in_flight.front().expect("a batch in flight").ticket);
+            self.wait(device, oldest)?;
+        };
+
+        let offset = start % self.capacity;
+        self.mapped
+            .add(offset as usize)
+            .copy_from_nonoverlapping(data.as_ptr(), data.len());
+        self.head = start + size;
+        Ok(offset)
+    }
+}
+
+impl OwnershipTransfer {
+    /// Submits the release batch signaling a semaphore, then the acquire barriers on the graphics queue
+    /// waiting on it and signaling `completion`.
+    unsafe fn submit(
+        &mut self,
+        device: &ash::Device,
+        transfer_queue: vk::Queue,
+        release: vk::CommandBuffer,
+        completion: Completion,
+    ) -> VkResult<(vk::CommandBuffer, vk::Semaphore)> {
+        let semaphore = match self.free_semaphores.pop() {
+            Some(semaphore) => semaphore,
+            None => {
+                let semaphore_info = vk::SemaphoreCreateInfo {
+                    s_type: StructureType::SEMAPHORE_CREATE_INFO,
+                    p_next: ptr::null(),
+                    flags: vk::SemaphoreCreateFlags::empty(),
+                };
+                device.create_semaphore(&semaphore_info, None)?
+            }
+        };
+        submit(device, transfer_queue, release, None, Some(semaphore), None)?;
+
+        let acquire = begin_pooled(device, self.command_pool, &mut self.free_command_buffers)?;
+        device.cmd_pipeline_barrier(
+            acquire,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &self.buffer_acquires,
+            &self.image_acquires,
+        );
+        for chain in self.mip_chains.drain(..) {
+            chain.record(device, acquire);
+        }
+        device.end_command_buffer(acquire)?;
+        self.buffer_acquires.clear();
+        self.image_acquires.clear();
+
+        submit(device, self.graphics_queue, acquire, Some(semaphore), None, Some(completion))?;
+        Ok((acquire, semaphore))
+    }
+}
+
+/// How the last submission of a batch reports that it finished.
+#[derive(Clone, Copy)]
+enum Completion {
+    Fence(vk::Fence),
+    /// semaphore and the value it is signaled with
+    Timeline(vk::Semaphore, u64),
+}
+
+/// Submits one command buffer, optionally waiting on and signaling a binary semaphore.
+unsafe fn submit(
+    device: &ash::Device,
+    queue: vk::Queue,
+    command_buffer: vk::CommandBuffer,
+    wait: Option<vk::Semaphore>,
+    signal: Option<vk::Semaphore>,
+    completion: Option<Completion>,
+) -> VkResult<()> {
+    let wait_semaphores: Vec<vk::Semaphore> = wait.into_iter().collect();
+    let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+    let wait_values = vec![0; wait_semaphores.len()];
+    let mut signal_semaphores: Vec<vk::Semaphore> = signal.into_iter().collect();
+    // binary semaphores ignore their value
+    let mut signal_values = vec![0; signal_semaphores.len()];
+    let mut fence = vk::Fence::null();
+    match completion {
+        Some(Completion::Fence(completion_fence)) => fence = completion_fence,
+        Some(Completion::Timeline(timeline, value)) => {
+            signal_semaphores.push(timeline);
+            signal_values.push(value);
+        }
+        None => {}
+    }
+
+    let timeline_info = vk::TimelineSemaphoreSubmitInfo {
+        s_type: StructureType::TIMELINE_SEMAPHORE_SUBMIT_INFO,
+        p_next: ptr::null(),
+        wait_semaphore_value_count: wait_values.len() as u32,
+        p_wait_semaphore_values: wait_values.as_ptr(),
+        signal_semaphore_value_count: signal_values.len() as u32,
+        p_signal_semaphore_values: signal_values.as_ptr(),
+    };
+    let uses_timeline = matches!(completion, Some(Completion::Timeline(..)));
+    let submit_info = vk::SubmitInfo {
+        s_type: StructureType::SUBMIT_INFO,
+        p_next: if uses_timeline {
+            &timeline_info as *const vk::TimelineSemaphoreSubmitInfo as *const c_void
+        } else {
+            ptr::null()
+        },
+        wait_semaphore_count: wait_semaphores.len() as u32,
+        p_wait_semaphores: wait_semaphores.as_ptr(),
+        p_wait_dst_stage_mask: wait_stages.as_ptr(),
+        command_buffer_count: 1,
+        p_command_buffers: &command_buffer,
+        signal_semaphore_count: signal_semaphores.len() as u32,
+        p_signal_semaphores: signal_semaphores.as_ptr(),
+    };
+    device.queue_submit(queue, &[submit_info], fence)
+}
+
+unsafe fn create_transient_pool(device: &ash::Device, queue_family: u32) -> VkResult<vk::CommandPool> {
+    let pool_info = vk::CommandPoolCreateInfo {
+        s_type: StructureType::COMMAND_POOL_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER | vk::CommandPoolCreateFlags::TRANSIENT,
+        queue_family_index: queue_family,
+    };
+    device.create_command_pool(&pool_info, None)
+}
+
+/// Reuses a command buffer from `free` or allocates one from `pool`, and begins it for one submission.
+unsafe fn begin_pooled(
+    device: &ash::Device,
+    pool: vk::CommandPool,
+    free: &mut Vec<vk::CommandBuffer>,
+) -> VkResult<vk::CommandBuffer> {
+    let command_buffer = match free.pop() {
+        Some(command_buffer) => {
+            device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            command_buffer
+        }
+        None => {
+            let alloc_info = vk::CommandBufferAllocateInfo {
+                s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+                p_next: ptr::null(),
+                command_pool: pool,
+                level: vk::CommandBufferLevel::PRIMARY,
+                command_buffer_count: 1,
+            };
+            device.allocate_command_buffers(&alloc_info)?[0]
+        }
+    };
+    let begin_info = vk::CommandBufferBeginInfo {
+        s_type: StructureType::COMMAND_BUFFER_BEGIN_INFO,
+        p_next: ptr::null(),
+        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        p_inheritance_info: ptr::null(),
+    };
+    device.begin_command_buffer(command_buffer, &begin_info)?;
+    Ok(command_buffer)
+}
+
+/// Bytes per texel of tightly packed `data` covering `extent`, an error if it does not divide evenly.
+fn texel_size(data: &[u8], extent: vk::Extent3D) -> Result<usize> {
+    let texels = extent.width as usize * extent.height as usize * extent.depth as usize;
+    if texels == 0 || data.is_empty() || !data.len().is_multiple_of(texels) {
+        return Err(Error::msg(format!(
+            "{} bytes are not tightly packed texels of a {}x{}x{} image",
+            data.len(),
+            extent.width,
+            extent.height,
+            extent.depth
+        )));
+    }
+    Ok(data.len() / texels)
+}
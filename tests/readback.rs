@@ -0,0 +1,264 @@
+use std::ptr;
+
+use ash::vk::{self, StructureType};
+use vulky::{
+    allocator::MemoryAllocator,
+    deletion::DeletionQueue,
+    device::DeviceFeatures,
+    gpu_buffer::Buffer,
+    readback::{BufferRead, ReadbackContext},
+    upload::{UploadContext, DEFAULT_STAGING_SIZE},
+    FamilyQueue,
+};
+
+/// Instance and device without a surface, e.g. on lavapipe. None when there is no vulkan device.
+unsafe fn headless_device() -> Option<(ash::Instance, vk::PhysicalDevice, ash::Device, u32)> {
+    let entry = ash::Entry::linked();
+    let application_name = c"readback test";
+    let app_info = vk::ApplicationInfo {
+        s_type: StructureType::APPLICATION_INFO,
+        p_next: ptr::null(),
+        p_application_name: application_name.as_ptr(),
+        application_version: 0,
+        p_engine_name: application_name.as_ptr(),
+        engine_version: 0,
+        api_version: vk::API_VERSION_1_2,
+    };
+    let instance_info = vk::InstanceCreateInfo {
+        s_type: StructureType::INSTANCE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::InstanceCreateFlags::empty(),
+        p_application_info: &app_info,
+        enabled_layer_count: 0,
+        pp_enabled_layer_names: ptr::null(),
+        enabled_extension_count: 0,
+        pp_enabled_extension_names: ptr::null(),
+    };
+    let instance = entry.create_instance(&instance_info, None).ok()?;
+
+    // any family with graphics or compute can copy buffers
+    let found = instance
+        .enumerate_physical_devices()
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|physical_device| {
+            instance
+                .get_physical_device_queue_family_properties(physical_device)
+                .iter()
+                .position(|family| {
+                    family
+                        .queue_flags
+                        .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+                })
+                .map(|family| (physical_device, family as u32))
+        });
+    let Some((physical_device, queue_family)) = found else {
+        instance.destroy_instance(None);
+        return None;
+    };
+
+    let queue_priorities = [1.0];
+    let queue_info = vk::DeviceQueueCreateInfo {
+        s_type: StructureType::DEVICE_QUEUE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DeviceQueueCreateFlags::empty(),
+        queue_family_index: queue_family,
+        queue_count: 1,
+        p_queue_priorities: queue_priorities.as_ptr(),
+    };
+    let device_info = vk::DeviceCreateInfo {
+        s_type: StructureType::DEVICE_CREATE_INFO,
+        queue_create_info_count: 1,
+        p_queue_create_infos: &queue_info,
+        ..Default::default()
+    };
+    match instance.create_device(physical_device, &device_info, None) {
+        Ok(device) => Some((instance, physical_device, device, queue_family)),
+        Err(_) => {
+            instance.destroy_instance(None);
+            None
+        }
+    }
+}
+
+/// Everything a readback test needs, on one queue.
+struct Gpu {
+    instance: ash::Instance,
+    device: ash::Device,
+    queue: FamilyQueue,
+    allocator: MemoryAllocator,
+    deletion: DeletionQueue,
+    uploads: UploadContext,
+    readback: ReadbackContext,
+}
+
+impl Gpu {
+    unsafe fn new() -> Option<Self> {
+        let Some((instance, physical_device, device, queue_family)) = headless_device() else {
+            eprintln!("no vulkan device, skipping");
+            return None;
+        };
+        let queue = FamilyQueue {
+            family: queue_family,
+            queue: device.get_device_queue(queue_family, 0),
+        };
+        // no optional features, the upload falls back to fences
+        let features = DeviceFeatures::default();
+        let allocator = MemoryAllocator::new(&instance, physical_device, &features);
+        let uploads = UploadContext::new(
+            &device,
+            &instance,
+            physical_device,
+            queue,
+            queue,
+            &features,
+            DEFAULT_STAGING_SIZE,
+        )
+        .unwrap();
+        let readback = ReadbackContext::new(&device, queue_family).unwrap();
+        Some(Self {
+            instance,
+            device,
+            queue,
+            allocator,
+            deletion: DeletionQueue::new(),
+            uploads,
+            readback,
+        })
+    }
+
+    /// Device local buffer holding `data`, uploaded and ready to read.
+    unsafe fn buffer(&mut self, data: &[u32]) -> Buffer<u32> {
+        let buffer = Buffer::with_data(
+            &self.device,
+            &mut self.allocator,
+            &self.deletion,
+            &mut self.uploads,
+            data,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        )
+        .unwrap();
+        let ticket = self.uploads.flush(&self.device).unwrap();
+        self.uploads.wait(&self.device, ticket).unwrap();
+        buffer
+    }
+
+    /// Resources made from the allocator have to be dropped already.
+    unsafe fn destroy(mut self) {
+        self.device.device_wait_idle().unwrap();
+        self.deletion.flush(&self.device, &mut self.allocator);
+        self.readback.destroy(&self.device, &mut self.allocator).unwrap();
+        self.uploads.destroy(&self.device);
+        assert_eq!(self.allocator.stats().allocations, 0, "everything was freed");
+        self.allocator.destroy(&self.device);
+        self.device.destroy_device(None);
+        self.instance.destroy_instance(None);
+    }
+}
+
+fn values(count: u32) -> Vec<u32> {
+    (0..count).map(|value| value * 3 + 1).collect()
+}
+
+#[test]
+fn upload_and_read_back() {
+    unsafe {
+        let Some(mut gpu) = Gpu::new() else {
+            return;
+        };
+        let data = values(10_000);
+        let buffer = gpu.buffer(&data);
+
+        let read = gpu.readback.read(&gpu.device, &mut gpu.allocator, &buffer).unwrap();
+        assert_eq!(read, data);
+
+        drop(buffer);
+        gpu.destroy();
+    }
+}
+
+#[test]
+fn read_waits_on_semaphores() {
+    unsafe {
+        let Some(mut gpu) = Gpu::new() else {
+            return;
+        };
+        let data = values(1000);
+        let buffer = gpu.buffer(&data);
+
+        // an empty submission stands in for work producing the buffer
+        let semaphore_info = vk::SemaphoreCreateInfo {
+            s_type: StructureType::SEMAPHORE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::SemaphoreCreateFlags::empty(),
+        };
+        let semaphore = gpu.device.create_semaphore(&semaphore_info, None).unwrap();
+        let submit_info = vk::SubmitInfo {
+            s_type: StructureType::SUBMIT_INFO,
+            signal_semaphore_count: 1,
+            p_signal_semaphores: &semaphore,
+            ..Default::default()
+        };
+        gpu.device
+            .queue_submit(gpu.queue.queue, &[submit_info], vk::Fence::null())
+            .unwrap();
+
+        // a range in the middle, after the semaphore
+        let read = BufferRead {
+            src: buffer.handle(),
+            offset: 100 * 4,
+            count: 50,
+            wait_semaphores: &[(semaphore, vk::PipelineStageFlags::TRANSFER)],
+        };
+        let readback = gpu
+            .readback
+            .read_buffer::<u32>(&gpu.device, &mut gpu.allocator, &read)
+            .unwrap();
+        let read = readback.into_vec(&gpu.device, &mut gpu.allocator).unwrap();
+        assert_eq!(read, data[100..150]);
+
+        gpu.device.destroy_semaphore(semaphore, None);
+        drop(buffer);
+        gpu.destroy();
+    }
+}
+
+#[test]
+fn dropped_readbacks_are_freed() {
+    unsafe {
+        let Some(mut gpu) = Gpu::new() else {
+            return;
+        };
+        let data = values(1000);
+        let buffer = gpu.buffer(&data);
+        let allocations = gpu.allocator.stats().allocations;
+        let read = BufferRead {
+            src: buffer.handle(),
+            offset: 0,
+            count: data.len(),
+            wait_semaphores: &[],
+        };
+
+        // freed by the next read once its copy finished
+        let dropped = gpu
+            .readback
+            .read_buffer::<u32>(&gpu.device, &mut gpu.allocator, &read)
+            .unwrap();
+        assert_eq!(gpu.allocator.stats().allocations, allocations + 1);
+        dropped.wait(&gpu.device).unwrap();
+        drop(dropped);
+        assert_eq!(gpu.allocator.stats().allocations, allocations + 1);
+        let read_back = gpu.readback.read(&gpu.device, &mut gpu.allocator, &buffer).unwrap();
+        assert_eq!(read_back, data);
+        assert_eq!(gpu.allocator.stats().allocations, allocations);
+
+        // still in flight when dropped, destroy waits for it
+        drop(
+            gpu.readback
+                .read_buffer::<u32>(&gpu.device, &mut gpu.allocator, &read)
+                .unwrap(),
+        );
+        drop(buffer);
+        gpu.destroy();
+    }
+}